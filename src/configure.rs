@@ -1,13 +1,17 @@
 use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::path::Path;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
 
 use chrono::prelude::*;
 use hashbrown::HashMap;
 use serde::{Deserialize, Serialize};
 
+use crate::emulator::memory_pattern::{MemoryPattern, MemoryPatternConfig};
 use crate::emulator::register_pattern::{RegisterPattern, RegisterPatternConfig};
 use crate::error::Error;
+use crate::fitness::{Expressions, Representation};
 
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct DataConfig {
@@ -62,6 +66,12 @@ pub struct Config {
     #[serde(default = "default_one")]
     pub mutation_rate: f64,
     pub mutation_exponent: f64,
+    // Genes that have been inherited gene_ttl times without being executed
+    // are replaced by mutation. None disables this.
+    #[serde(default)]
+    pub gene_ttl: Option<usize>,
+    #[serde(default)]
+    pub mutation_bias: MutationBias,
     pub observer: ObserverConfig,
     pub pop_size: usize,
     pub problems: Option<Vec<IOProblem>>,
@@ -69,6 +79,20 @@ pub struct Config {
     pub roulette: RouletteConfig,
     #[serde(default)]
     pub tournament: TournamentConfig,
+    #[serde(default)]
+    pub lexicase: LexicaseConfig,
+    #[serde(default)]
+    pub novelty: NoveltyConfig,
+    #[serde(default)]
+    pub alps: AlpsConfig,
+    #[serde(default)]
+    pub metropolis: MetropolisConfig,
+    #[serde(default)]
+    pub map_elites: MapElitesConfig,
+    #[serde(default)]
+    pub seeding: SeedingConfig,
+    #[serde(default)]
+    pub migration: MigrationConfig,
     #[serde(default = "Default::default")]
     pub roper: RoperConfig,
     #[serde(default = "Default::default")]
@@ -79,10 +103,35 @@ pub struct Config {
     pub fitness: FitnessConfig,
     #[serde(default = "default_random_seed")]
     pub random_seed: u64,
+    // In reproducible mode, two runs with the same random_seed evolve
+    // identical populations. This costs us parallelism between islands,
+    // since migration and the epoch counter depend on thread timing.
+    #[serde(default)]
+    pub reproducible: bool,
     #[serde(default)]
     pub push_vm: PushVm,
 }
 
+/// Where mutation should be aimed, according to the part each gene played
+/// the last time its lineage was executed.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum MutationBias {
+    /// Every gene is equally likely to be mutated.
+    Uniform,
+    /// Mutate the first gene that execution never reached.
+    FirstUnexecuted,
+    /// Leave the executed prefix of the chromosome alone.
+    ProtectExecuted,
+    /// Mutate only the genes that were consumed as data.
+    Consumed,
+}
+
+impl Default for MutationBias {
+    fn default() -> Self {
+        Self::Uniform
+    }
+}
+
 fn default_tournament_size() -> usize {
     4
 }
@@ -104,10 +153,122 @@ pub struct PushVm {
 pub struct FitnessConfig {
     pub target: f64,
     pub eval_by_case: bool,
+    /// In dynamic mode, the fitness function is applied afresh to creatures
+    /// whose scores are at least `reevaluate_every` epochs old, whenever they
+    /// come up for selection. Otherwise, it's applied only once.
     pub dynamic: bool,
+    #[serde(default)]
+    pub reevaluate_every: usize,
+    /// Have dynamic re-evaluation execute the creatures again, too.
+    #[serde(default)]
+    pub re_execute: bool,
     pub priority: String,
     pub function: String,
     pub weighting: String,
+    /// The fitness components to measure, when `function` is "components".
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// How fitness scores are compared during selection.
+    #[serde(default)]
+    pub representation: Representation,
+    /// Sub-tasks to be completed, in order, before the objective given
+    /// by the fields above is pursued.
+    #[serde(default)]
+    pub stages: Vec<StageConfig>,
+    /// The index of the stage an island is currently working on. This is
+    /// shared by every clone of an island's config, so that the observer
+    /// can advance the stage for the island's evaluator.
+    #[serde(skip)]
+    pub current_stage: Arc<AtomicUsize>,
+    /// The weighting and priority expressions, compiled, and shared by
+    /// every clone of the config.
+    #[serde(skip)]
+    pub expressions: Arc<Expressions>,
+}
+
+/// A stage is complete once the island's champion scores no more than
+/// `target` on the `priority` expression.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StageConfig {
+    pub function: String,
+    pub weighting: String,
+    #[serde(default)]
+    pub components: Vec<String>,
+    pub priority: String,
+    #[serde(default)]
+    pub target: f64,
+    /// Restricts the register pattern to these registers, for the
+    /// duration of the stage.
+    pub registers: Option<Vec<String>>,
+}
+
+impl FitnessConfig {
+    /// Compiles the weighting and priority expressions of the objective and
+    /// of each stage up front, so that any errors in them show up at once.
+    pub fn compile_expressions(&self) -> Result<(), Error> {
+        for expr in self
+            .stages
+            .iter()
+            .flat_map(|s| vec![&s.weighting, &s.priority])
+            .chain(vec![&self.weighting, &self.priority])
+        {
+            self.expressions.get(expr)?;
+        }
+        Ok(())
+    }
+
+    /// Once every stage has been completed, this will equal the number
+    /// of stages.
+    pub fn stage(&self) -> usize {
+        self.current_stage.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn is_final_stage(&self) -> bool {
+        self.stage() >= self.stages.len()
+    }
+
+    /// Moves on to the next stage, returning false if there's
+    /// nowhere left to go.
+    pub fn advance_stage(&self) -> bool {
+        if self.is_final_stage() {
+            false
+        } else {
+            self.current_stage.fetch_add(1, atomic::Ordering::Relaxed);
+            true
+        }
+    }
+
+    /// Gives the island a fresh stage counter, so that it doesn't share
+    /// its progress with the config it was cloned from.
+    pub fn reset_stage(&mut self) {
+        self.current_stage = Arc::new(AtomicUsize::new(0));
+    }
+
+    fn current(&self) -> Option<&StageConfig> {
+        self.stages.get(self.stage())
+    }
+
+    pub fn function(&self) -> &str {
+        self.current().map_or(&self.function, |s| &s.function)
+    }
+
+    pub fn weighting(&self) -> &str {
+        self.current().map_or(&self.weighting, |s| &s.weighting)
+    }
+
+    pub fn priority(&self) -> &str {
+        self.current().map_or(&self.priority, |s| &s.priority)
+    }
+
+    pub fn target(&self) -> f64 {
+        self.current().map_or(self.target, |s| s.target)
+    }
+
+    pub fn registers(&self) -> Option<&[String]> {
+        self.current()
+            .and_then(|s| s.registers.as_ref())
+            .map(Vec::as_slice)
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -118,6 +279,89 @@ pub struct TournamentConfig {
     pub migration_rate: f64,
     pub num_offspring: usize,
     pub num_parents: usize,
+    #[serde(default)]
+    pub fitness_sharing: Option<SharingConfig>,
+    #[serde(default)]
+    pub permadeath: Option<PermadeathConfig>,
+    #[serde(default)]
+    pub adaptive_radius: Option<AdaptiveRadiusConfig>,
+}
+
+/// The measures of diversity that can drive the adaptive radius. Both
+/// range from 0.0 (none) to 1.0.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum DiversityMeasure {
+    /// The Shannon entropy of the alleles in the recent combatants'
+    /// genotypes, normalised by its maximum.
+    GenotypeEntropy,
+    /// The number of distinct gadgets executed by the recent combatants,
+    /// as a fraction of the number executed in all.
+    UniqueGadgets,
+}
+
+/// Adjusts the geographic radius in response to the diversity of the last
+/// `window` combatants, measured once every `window` tournaments. When it
+/// falls below `low`, the radius shrinks by `step`, isolating the demes
+/// from one another; when it rises above `high`, the radius grows by `step`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AdaptiveRadiusConfig {
+    pub measure: DiversityMeasure,
+    pub min_radius: usize,
+    pub max_radius: usize,
+    pub low: f64,
+    pub high: f64,
+    pub window: usize,
+    pub step: usize,
+}
+
+/// Shrinking populations: every `every`th tournament, the losers die
+/// without being replaced by offspring, until only `min_pop_size`
+/// creatures remain on the island.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PermadeathConfig {
+    pub every: usize,
+    pub min_pop_size: usize,
+}
+
+/// The measures of distance between creatures that fitness sharing
+/// can use.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum Distance {
+    /// The Jaccard distance between the sets of alleles.
+    Chromosome,
+    /// The Jaccard distance between the sets of gadgets executed.
+    Gadgets,
+    /// The Hamming distance between the final register values.
+    Registers,
+}
+
+/// Who a combatant shares its fitness with.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum SharingScope {
+    /// The other combatants in the tournament.
+    Tournament,
+    /// The whole neighbourhood that the combatants were drawn from.
+    Deme,
+}
+
+impl Default for SharingScope {
+    fn default() -> Self {
+        Self::Tournament
+    }
+}
+
+/// Fitness sharing divides the population into niches. Each combatant's
+/// rank in a tournament is multiplied by its niche count: the sum, over
+/// the creatures it shares with, of `1 - (d / radius)^alpha`, for each
+/// one closer than `radius`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SharingConfig {
+    pub distance: Distance,
+    pub radius: f64,
+    #[serde(default = "default_one")]
+    pub alpha: f64,
+    #[serde(default)]
+    pub scope: SharingScope,
 }
 
 fn default_weight_decay() -> f64 {
@@ -130,9 +374,322 @@ pub struct RouletteConfig {
     pub weight_decay: f64,
 }
 
+/// How close to the best error on a fitness case a creature must come
+/// in order to survive that case, in lexicase selection.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub enum Epsilon {
+    /// Plain lexicase selection: only the elite survive each case.
+    Exact,
+    Fixed(f64),
+    /// Epsilon-lexicase selection, with epsilon set to the median absolute
+    /// deviation of the errors on the case among the remaining candidates.
+    MedianAbsoluteDeviation,
+}
+
+impl Default for Epsilon {
+    fn default() -> Self {
+        Self::Exact
+    }
+}
+
+/// Lexicase selection takes its parent and offspring counts, and its
+/// migration rate, from the `tournament` section.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LexicaseConfig {
+    #[serde(default)]
+    pub epsilon: Epsilon,
+}
+
+/// Settings for the novelty archive, used by the "novelty" fitness
+/// component and the "novelty_search" fitness function.
+#[derive(Clone, Debug, Deserialize)]
+pub struct NoveltyConfig {
+    /// The number of nearest neighbours whose distances are averaged.
+    pub k: usize,
+    /// The sparseness a behaviour needs to be added to the archive.
+    pub threshold: f64,
+    /// The largest the archive may grow. The oldest entries go first.
+    pub capacity: usize,
+    /// The number of recently evaluated behaviours that are compared
+    /// against, alongside the archive.
+    pub window: usize,
+    /// Dump the archive after this many additions. 0 means never.
+    #[serde(default)]
+    pub dump_every: usize,
+}
+
+impl Default for NoveltyConfig {
+    fn default() -> Self {
+        Self {
+            k: 15,
+            threshold: 0.2,
+            capacity: 1000,
+            window: 500,
+            dump_every: 0,
+        }
+    }
+}
+
+/// How the temperature of a Metropolis replica falls as the search goes on.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Cooling {
+    /// The temperature never changes.
+    Constant,
+    /// The temperature falls by this much each iteration.
+    Linear(f64),
+    /// The temperature is multiplied by this rate, below 1.0, each iteration.
+    Exponential(f64),
+    /// Every `window` iterations, the temperature is divided by `factor` if
+    /// more than `target_acceptance` of the variations proposed in that
+    /// time were accepted, and multiplied by it otherwise.
+    Adaptive {
+        target_acceptance: f64,
+        window: usize,
+        factor: f64,
+    },
+}
+
+impl Default for Cooling {
+    fn default() -> Self {
+        Self::Constant
+    }
+}
+
+/// Settings for Metropolis local search. With more than one replica, this
+/// becomes parallel tempering: replica `i` starts at `temperature *
+/// temperature_ratio^i`, and neighbouring replicas may trade specimens
+/// every `swap_every` iterations. The temperatures never cool below
+/// `min_temperature`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MetropolisConfig {
+    pub temperature: f64,
+    pub cooling: Cooling,
+    pub min_temperature: f64,
+    pub replicas: usize,
+    pub temperature_ratio: f64,
+    pub swap_every: usize,
+}
+
+impl Default for MetropolisConfig {
+    fn default() -> Self {
+        Self {
+            temperature: 1.0,
+            cooling: Cooling::Constant,
+            min_temperature: 1e-6,
+            replicas: 1,
+            temperature_ratio: 2.0,
+            swap_every: 10,
+        }
+    }
+}
+
+/// Somewhere to find chromosomes with which to seed the initial population.
+#[derive(Clone, Debug, Deserialize)]
+pub enum SeedSource {
+    /// A population dumped by the observer, like `population_N.json.gz`.
+    Population(String),
+    /// A `champions` directory from an earlier run. Every champion dumped
+    /// there is used, except the `latest_champion` symlink.
+    Champions(String),
+    /// A text file of hand-written chains, one word per line, in hex
+    /// (with a leading `0x`) or decimal. Chains are separated by blank
+    /// lines, and anything after a `#` is a comment.
+    Chains(String),
+}
+
+/// Settings for seeding the initial population. If any seeds can be
+/// loaded from the `sources`, they make up `proportion` of the initial
+/// population, repeating if there are too few, and random creatures make
+/// up the rest.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SeedingConfig {
+    pub sources: Vec<SeedSource>,
+    pub proportion: f64,
+}
+
+impl Default for SeedingConfig {
+    fn default() -> Self {
+        Self {
+            sources: vec![],
+            proportion: 0.5,
+        }
+    }
+}
+
+/// A behavioural feature, measured from a creature's profile, along which
+/// the MAP-Elites archive is divided.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Feature {
+    /// The number of distinct gadgets executed.
+    GadgetsExecuted,
+    /// The most basic blocks executed on any one input.
+    ChainLength,
+    /// The most syscalls reached on any one input.
+    Syscalls,
+    /// A bitmask of the registers in the register pattern that were
+    /// matched on every input, taken in alphabetical order.
+    RegistersMatched,
+}
+
+/// One dimension of the MAP-Elites archive. A feature value `v` falls into
+/// bin `floor(v / bin_width)`, and anything past the last bin into the last.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FeatureDimension {
+    pub feature: Feature,
+    pub bins: usize,
+    #[serde(default = "default_one")]
+    pub bin_width: f64,
+}
+
+/// Settings for MAP-Elites. The archive begins with `pop_size` random
+/// creatures, and each iteration breeds `batch_size` offspring from the
+/// elites, with parents drawn uniformly from the occupied cells.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MapElitesConfig {
+    pub dimensions: Vec<FeatureDimension>,
+    pub batch_size: usize,
+    /// Dump the archive, and its heatmap, every `dump_every` iterations.
+    /// 0 means never.
+    pub dump_every: usize,
+}
+
+impl Default for MapElitesConfig {
+    fn default() -> Self {
+        Self {
+            dimensions: vec![
+                FeatureDimension {
+                    feature: Feature::GadgetsExecuted,
+                    bins: 32,
+                    bin_width: 1.0,
+                },
+                FeatureDimension {
+                    feature: Feature::ChainLength,
+                    bins: 32,
+                    bin_width: 4.0,
+                },
+            ],
+            batch_size: 64,
+            dump_every: 100,
+        }
+    }
+}
+
+/// Settings for the age-layered population structure (ALPS). The
+/// population is split evenly among the layers, and tournaments are
+/// otherwise configured by [tournament].
+#[derive(Clone, Debug, Deserialize)]
+pub struct AlpsConfig {
+    pub num_layers: usize,
+    /// The bottom layer is reseeded every `age_gap` generations, and the
+    /// age limits of the layers are multiples of it: 1, 2, 4, 9, 16, ...
+    /// The top layer has no age limit.
+    pub age_gap: usize,
+}
+
+impl Default for AlpsConfig {
+    fn default() -> Self {
+        Self {
+            num_layers: 5,
+            age_gap: 10,
+        }
+    }
+}
+
+impl AlpsConfig {
+    /// The oldest a creature may be and remain in the given layer, or
+    /// `None` for the top layer.
+    pub fn age_limit(&self, layer: usize) -> Option<usize> {
+        if layer + 1 >= self.num_layers {
+            None
+        } else if layer < 2 {
+            Some(self.age_gap * (layer + 1))
+        } else {
+            Some(self.age_gap * layer * layer)
+        }
+    }
+}
+
+/// The shape of the network along which creatures migrate between islands.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum Topology {
+    /// Each island exchanges with the islands on either side of it.
+    Ring,
+    /// The islands are laid out on a wrapping grid, and each exchanges
+    /// with the four islands adjacent to it.
+    Torus,
+    /// Island 0 exchanges with every other island, and they only with it.
+    Star,
+    /// Every island exchanges with every other.
+    Full,
+    /// Each island sends to, and receives from, up to the given number of
+    /// randomly chosen islands.
+    RandomRegular(usize),
+}
+
+/// Which of the survivors of a tournament is sent to another island.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum EmigrantPolicy {
+    Best,
+    /// The least fit of the survivors, which is who was sent before
+    /// emigrant policies could be chosen.
+    Worst,
+    Random,
+    /// The one furthest, on average, from the other survivors, as
+    /// measured by `migration.distance`.
+    MostNovel,
+}
+
+/// What happens when an immigrant arrives.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum ReplacementPolicy {
+    /// The emigrant leaves its island, and the immigrant simply joins
+    /// the survivors of the tournament.
+    Join,
+    /// The emigrant is a copy, and the immigrant takes the place of the
+    /// worst survivor of the tournament.
+    Worst,
+    /// The emigrant is a copy, and the immigrant takes the place of a
+    /// randomly chosen survivor of the tournament.
+    Random,
+}
+
+/// Settings for migration between islands. How often migration is
+/// attempted is set by `tournament.migration_rate`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MigrationConfig {
+    pub topology: Topology,
+    /// The number of creatures each edge of the network can hold.
+    pub edge_capacity: usize,
+    pub emigrant: EmigrantPolicy,
+    pub replacement: ReplacementPolicy,
+    /// The measure used by the `MostNovel` emigrant policy.
+    pub distance: Distance,
+    /// Whether to record every migration in `migration.csv`, in the
+    /// population's data directory.
+    pub log: bool,
+}
+
+impl Default for MigrationConfig {
+    fn default() -> Self {
+        Self {
+            topology: Topology::Full,
+            edge_capacity: 1,
+            emigrant: EmigrantPolicy::Worst,
+            replacement: ReplacementPolicy::Join,
+            distance: Distance::Chromosome,
+            log: true,
+        }
+    }
+}
+
 fn random_population_name() -> String {
-    // we're letting this random value be unseeded for now, since
-    // the name impacts nothing and we don't want to clobber same-seeded runs
+    // we're letting this random value be unseeded, even in reproducible
+    // mode, since the name impacts nothing and we don't want to clobber
+    // same-seeded runs
     let seed = rand::random::<u64>();
     crate::util::name::random(2, seed)
 }
@@ -146,6 +703,8 @@ pub struct ObserverConfig {
     pub dump_every: usize,
     #[serde(default)]
     pub full_data_directory: String,
+    #[serde(default)]
+    pub population_directory: String,
     data_directory: String,
     #[serde(default = "random_population_name")]
     pub population_name: String,
@@ -161,6 +720,8 @@ impl Config {
             config.observer.population_name = population_name;
         }
         config.assert_invariants();
+        config.fitness.compile_expressions()?;
+        config.roper.word_mutation.check_point_weights()?;
         config.set_data_directory();
         // copy the config file to the data directory for posterity
         // bit ugly, here: copying it to the parent of the directory, just above the island subdirs
@@ -186,16 +747,26 @@ impl Config {
         };
 
         let path = format!(
-            "{data_dir}/berbalang/{job:?}/{selection:?}/{date}/{pop_name}/island_{island}",
+            "{data_dir}/berbalang/{job:?}/{selection:?}/{date}/{pop_name}",
             data_dir = data_dir,
             job = self.job,
             selection = self.selection,
             date = local_date.format("%Y/%m/%d"),
             pop_name = self.observer.population_name,
-            island = self.island_identifier,
         );
+        self.observer.population_directory = path.clone();
+        let path = format!("{}/island_{}", path, self.island_identifier);
 
-        for sub in ["", "soup", "population", "champions"].iter() {
+        for sub in [
+            "",
+            "soup",
+            "population",
+            "champions",
+            "novelty",
+            "map_elites",
+        ]
+        .iter()
+        {
             let d = format!("{}/{}", path, sub);
             std::fs::create_dir_all(&d)
                 .map_err(|e| log::error!("Error creating {}: {:?}", path, e))
@@ -208,6 +779,12 @@ impl Config {
     pub fn data_directory(&self) -> &str {
         &self.observer.full_data_directory
     }
+
+    /// The directory holding the data directories of every island. Only
+    /// available once `set_data_directory` has been called.
+    pub fn population_directory(&self) -> &str {
+        &self.observer.population_directory
+    }
 }
 
 #[derive(Default, Clone, Debug, Deserialize)]
@@ -232,7 +809,7 @@ fn default_mode() -> unicorn::Mode {
     unicorn::Mode::MODE_32
 }
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct RoperConfig {
     #[serde(default)]
     pub use_push: bool,
@@ -241,9 +818,22 @@ pub struct RoperConfig {
     pub output_registers: Vec<String>,
     #[serde(default)]
     pub randomize_registers: bool,
+    /// The number of input states each creature is run on. Only makes
+    /// a difference when `randomize_registers` is set.
+    #[serde(default = "default_num_inputs")]
+    pub num_inputs: usize,
+    /// The chance that bare creatures are mated by trace-aware crossover,
+    /// which cuts only at gadget boundaries, rather than the usual kind.
+    #[serde(default)]
+    pub trace_crossover_rate: f64,
+    #[serde(default)]
+    pub word_mutation: WordMutationConfig,
     pub register_pattern: Option<RegisterPatternConfig>,
     #[serde(skip)]
     pub parsed_register_pattern: Option<RegisterPattern>,
+    pub memory_pattern: Option<MemoryPatternConfig>,
+    #[serde(skip)]
+    pub parsed_memory_pattern: Option<MemoryPattern>,
     #[serde(default = "Default::default")]
     pub soup: Option<Vec<u64>>,
     pub soup_size: Option<usize>,
@@ -273,6 +863,80 @@ pub struct RoperConfig {
     pub bad_bytes: Option<HashMap<String, u8>>,
 }
 
+/// The relative weights of the point mutations that can be applied to the
+/// words of a bare chromosome, and the chances of each kind of structural
+/// mutation. The point mutation weights mustn't all be zero.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WordMutationConfig {
+    pub dereference: f64,
+    pub indirection: f64,
+    pub address_add: f64,
+    pub address_sub: f64,
+    pub bit_flip: f64,
+    /// Replace the word with a random gadget from the soup.
+    pub soup_gadget: f64,
+    /// Replace the word with a gadget from the soup whose first
+    /// instruction is of the same kind, and writes the same registers.
+    pub similar_gadget: f64,
+    // The chances, each time a chromosome is mutated, of a structural
+    // mutation. At most one of these is applied at a time.
+    /// Insert a random gadget from the soup.
+    pub insert_gadget: f64,
+    pub delete_word: f64,
+    /// Copy a short run of words, and insert the copy after the original.
+    pub duplicate_run: f64,
+    pub swap_words: f64,
+}
+
+impl WordMutationConfig {
+    /// The weights of the point mutations, in the order in which the
+    /// mutations are declared.
+    pub fn point_weights(&self) -> [f64; 7] {
+        [
+            self.dereference,
+            self.indirection,
+            self.address_add,
+            self.address_sub,
+            self.bit_flip,
+            self.soup_gadget,
+            self.similar_gadget,
+        ]
+    }
+
+    /// Checks that the point mutations can be chosen between: no weight
+    /// may be negative, and they mustn't all be zero.
+    pub fn check_point_weights(&self) -> Result<(), Error> {
+        let weights = self.point_weights();
+        if weights.iter().any(|w| *w < 0.0) || weights.iter().sum::<f64>() <= 0.0 {
+            Err(Error::Parsing(format!(
+                "Bad word mutation weights: {:?}",
+                weights
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for WordMutationConfig {
+    fn default() -> Self {
+        Self {
+            dereference: 1.0,
+            indirection: 1.0,
+            address_add: 1.0,
+            address_sub: 1.0,
+            bit_flip: 1.0,
+            soup_gadget: 0.0,
+            similar_gadget: 0.0,
+            insert_gadget: 0.0,
+            delete_word: 0.0,
+            duplicate_run: 0.0,
+            swap_words: 0.0,
+        }
+    }
+}
+
 impl RoperConfig {
     pub fn parse_register_pattern(&mut self) {
         if let Some(ref rp) = self.register_pattern {
@@ -283,12 +947,26 @@ impl RoperConfig {
     pub fn register_pattern(&self) -> Option<&RegisterPattern> {
         self.parsed_register_pattern.as_ref()
     }
+
+    pub fn parse_memory_pattern(&mut self) {
+        if let Some(ref mp) = self.memory_pattern {
+            self.parsed_memory_pattern = Some(mp.into());
+        }
+    }
+
+    pub fn memory_pattern(&self) -> Option<&MemoryPattern> {
+        self.parsed_memory_pattern.as_ref()
+    }
 }
 
 fn default_num_workers() -> usize {
     num_cpus::get()
 }
 
+const fn default_num_inputs() -> usize {
+    1
+}
+
 fn default_num_emu() -> usize {
     default_num_workers() + 1
 }
@@ -308,8 +986,13 @@ impl Default for RoperConfig {
             gadget_file: None,
             output_registers: vec![],
             randomize_registers: false,
+            num_inputs: 1,
+            trace_crossover_rate: 0.0,
+            word_mutation: WordMutationConfig::default(),
             register_pattern: None,
             parsed_register_pattern: None,
+            memory_pattern: None,
+            parsed_memory_pattern: None,
             soup: None,
             soup_size: None,
             arch: unicorn::Arch::X86,
@@ -330,6 +1013,25 @@ impl Default for RoperConfig {
 }
 
 impl Config {
+    /// Rules out the settings under which a run can't be reproduced from
+    /// its random seed. Call this after the logger has been initialized,
+    /// so that the user hears about it.
+    pub fn make_reproducible(&mut self) {
+        if self.num_islands != 1 {
+            log::warn!(
+                "Reproducible mode needs a single island, not {}",
+                self.num_islands
+            );
+            self.num_islands = 1;
+        }
+        if let Some(timeout) = self.roper.millisecond_timeout {
+            log::warn!(
+                "A timeout of {}ms depends on the speed of the machine, and may spoil reproducibility",
+                timeout
+            );
+        }
+    }
+
     pub fn assert_invariants(&self) {
         assert!(self.tournament.tournament_size >= self.tournament.num_offspring + 2);
         //assert_eq!(self.num_offspring, 2); // all that's supported for now
@@ -363,6 +1065,8 @@ pub enum Selection {
     Roulette,
     Metropolis,
     Lexicase,
+    Alps,
+    MapElites,
 }
 
 impl Default for Selection {
@@ -370,3 +1074,53 @@ impl Default for Selection {
         Self::Tournament
     }
 }
+
+impl Selection {
+    /// Whether the islands of this kind of world exchange creatures
+    /// through a pier.
+    pub fn migrates(&self) -> bool {
+        match self {
+            Self::Tournament | Self::Lexicase | Self::Alps => true,
+            Self::Roulette | Self::Metropolis | Self::MapElites => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_point_weights() {
+        let mut rates = WordMutationConfig::default();
+        assert!(rates.check_point_weights().is_ok());
+
+        rates.bit_flip = -1.0;
+        assert!(rates.check_point_weights().is_err());
+
+        let rates = WordMutationConfig {
+            dereference: 0.0,
+            indirection: 0.0,
+            address_add: 0.0,
+            address_sub: 0.0,
+            bit_flip: 0.0,
+            soup_gadget: 0.0,
+            similar_gadget: 0.0,
+            insert_gadget: 1.0,
+            ..Default::default()
+        };
+        assert!(rates.check_point_weights().is_err());
+    }
+
+    #[test]
+    fn test_partial_migration_table() {
+        let migration: MigrationConfig =
+            toml::from_str("topology = \"Ring\"").expect("Failed to parse migration table");
+        assert_eq!(migration.topology, Topology::Ring);
+        let default = MigrationConfig::default();
+        assert_eq!(migration.edge_capacity, default.edge_capacity);
+        assert_eq!(migration.emigrant, default.emigrant);
+        assert_eq!(migration.replacement, default.replacement);
+        assert!(migration.log);
+    }
+}
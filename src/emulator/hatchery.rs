@@ -157,8 +157,8 @@ pub struct Hatchery<C: Cpu<'static> + Send, X: Pack + Sync + Send + 'static> {
     thread_pool: Arc<Mutex<ThreadPool>>,
     config: Arc<RoperConfig>,
     memory: Arc<Option<Pin<Vec<Seg>>>>,
-    tx: SyncSender<X>,
-    rx: Receiver<(X, Profile)>,
+    tx: SyncSender<(usize, X)>,
+    rx: Receiver<(usize, X, Profile)>,
     handle: JoinHandle<()>,
     disassembler: Arc<Disassembler>,
 }
@@ -203,8 +203,13 @@ impl<C: Cpu<'static> + Send, X: Pack + Sync + Send + 'static> Drop for Hatchery<
     }
 }
 
-type InboundChannel<T> = (SyncSender<T>, Receiver<T>);
-type OutboundChannel<T> = (SyncSender<(T, Profile)>, Receiver<(T, Profile)>);
+// Payloads are numbered on their way in, so that a batch can be put back in
+// order once the workers have finished with it.
+type InboundChannel<T> = (SyncSender<(usize, T)>, Receiver<(usize, T)>);
+type OutboundChannel<T> = (
+    SyncSender<(usize, T, Profile)>,
+    Receiver<(usize, T, Profile)>,
+);
 
 impl<C: 'static + Cpu<'static> + Send, X: Pack + Send + Sync + Debug + 'static> Hatchery<C, X> {
     pub fn new(
@@ -247,7 +252,7 @@ impl<C: 'static + Cpu<'static> + Send, X: Pack + Send + Sync + Debug + 'static>
                     .collect::<HashMap<u8, u8>>()
             }));
         let handle = spawn(move || {
-            for payload in our_rx.iter() {
+            for (index, payload) in our_rx.iter() {
                 let emu_prep_fn = emu_prep_fn.clone();
                 let config = parameters.clone();
                 let bad_bytes = bad_bytes.clone();
@@ -288,7 +293,7 @@ impl<C: 'static + Cpu<'static> + Send, X: Pack + Send + Sync + Debug + 'static>
                             let _hook = hooking::install_disas_tracer_hook(&mut (*emu), disas.clone(), output_registers.clone()).expect("Failed to install tracer hook");
                         }
 
-                        let _hook = hooking::install_syscall_hook(&mut (*emu), config.arch, config.mode);
+                        let _hook = hooking::install_syscall_hook(&mut (*emu), config.arch, config.mode, &profiler, output_registers.clone());
                         if config.record_memory_writes {
                             let _hooks = hooking::install_mem_write_hook(&mut (*emu), &profiler).expect("Failed to install mem_write_hook");
                         }
@@ -316,12 +321,7 @@ impl<C: 'static + Cpu<'static> + Send, X: Pack + Send + Sync + Debug + 'static>
                         profiler.read_registers(&mut emu);
 
                         // TODO : add this to the profiler
-                        let written_memory = tools::read_writeable_memory(&(*emu)).expect("Failed to read writeable memory").into_par_iter().filter(|seg| {
-                            // bit of a space/time tradeoff here. see how it goes.
-                            let stat = static_memory.try_dereference(seg.addr, None).unwrap();
-                            debug_assert_eq!(stat.len(), seg.data.len());
-                            stat != seg.data.as_slice()
-                        }).collect::<Vec<Seg>>();
+                        let written_memory = tools::read_written_memory(emu.emu()).expect("Failed to read writeable memory");
                         // could some sort of COW structure help here?
                         // TODO consider defining a data structure that looks, from the
                         // outside, like a vector, but which is actually composed of an
@@ -354,7 +354,7 @@ impl<C: 'static + Cpu<'static> + Send, X: Pack + Send + Sync + Debug + 'static>
                     }).collect::<Vec<Profiler<C>>>().into(); // into Profile
                     // Now send the code back, along with its profile information.
                     // (The genotype, along with its phenotype.)
-                    our_tx.send((payload, profile)).map_err(Error::from).expect("TX Failure in pipeline");
+                    our_tx.send((index, payload, profile)).map_err(Error::from).expect("TX Failure in pipeline");
                 });
             }
         });
@@ -371,8 +371,9 @@ impl<C: 'static + Cpu<'static> + Send, X: Pack + Send + Sync + Debug + 'static>
     }
 
     pub fn execute(&self, payload: X) -> Result<(X, Profile), Error> {
-        self.tx.send(payload)?;
-        self.rx.recv().map_err(Error::from)
+        self.tx.send((0, payload))?;
+        let (_, payload, profile) = self.rx.recv()?;
+        Ok((payload, profile))
     }
 
     pub fn execute_batch<I: Iterator<Item = X>>(
@@ -381,14 +382,21 @@ impl<C: 'static + Cpu<'static> + Send, X: Pack + Send + Sync + Debug + 'static>
     ) -> Result<Vec<(X, Profile)>, Error> {
         let mut count = 0;
         for x in payloads {
-            self.tx.send(x)?;
+            self.tx.send((count, x))?;
             count += 1;
         }
         let mut res = Vec::new();
         for _ in 0..count {
             res.push(self.rx.recv()?)
         }
-        Ok(res)
+        // The workers finish in whatever order the scheduler pleases, so
+        // the results are returned in the order the payloads were sent,
+        // and evaluation order can't leak into the fitness functions.
+        res.sort_by_key(|(index, _, _)| *index);
+        Ok(res
+            .into_iter()
+            .map(|(_, payload, profile)| (payload, profile))
+            .collect())
     }
 }
 // TODO: try to reduce the number of mutexes needed in this setup. it seems like a code smell.
@@ -398,19 +406,31 @@ pub mod tools {
 
     use super::*;
 
-    // Reads all memory that carries a Protection::WRITE permission.
-    // This can be used, e.g., to check to see what a specimen has written
-    // to memory.
-    pub fn read_writeable_memory<C: 'static + Cpu<'static>>(emu: &C) -> Result<Vec<Seg>, Error> {
-        emu.mem_regions()?
+    /// Reads the writeable memory, keeping only those segments that differ
+    /// from the static memory image, to see what a specimen has written to
+    /// memory. This works on the bare engine, so that it can also be called
+    /// from within a hook.
+    pub fn read_written_memory(engine: &unicorn::Unicorn<'_>) -> Result<Vec<Seg>, Error> {
+        let static_memory = loader::get_static_memory_image();
+        let segs = engine
+            .mem_regions()?
             .into_iter()
             .filter(MemRegion::writeable)
             .map(|mem_reg| {
-                emu.mem_read_as_vec(mem_reg.begin, mem_reg.size())
+                engine
+                    .mem_read_as_vec(mem_reg.begin, mem_reg.size())
                     .map(|data| Seg::from_mem_region_and_data(mem_reg, data))
             })
-            .collect::<Result<Vec<Seg>, unicorn::Error>>()
-            .map_err(Error::from)
+            .collect::<Result<Vec<Seg>, unicorn::Error>>()?;
+        Ok(segs
+            .into_par_iter()
+            .filter(|seg| {
+                // bit of a space/time tradeoff here. see how it goes.
+                let stat = static_memory.try_dereference(seg.addr, None).unwrap();
+                debug_assert_eq!(stat.len(), seg.data.len());
+                stat != seg.data.as_slice()
+            })
+            .collect())
     }
 
     /// Returns the uppermost readable/writeable memory region, in the emulator's
@@ -454,13 +474,19 @@ pub mod hooking {
         }
     }
 
-    /// We want the emulator to halt on a syscall.
+    /// We want the emulator to halt on a syscall. Before it does, we take
+    /// a snapshot of the output registers and of the memory written so far,
+    /// so that the arguments to the syscall can be inspected even if
+    /// something goes wrong afterwards.
     pub fn install_syscall_hook<C: 'static + Cpu<'static>>(
         emu: &mut C,
         arch: unicorn::Arch,
         mode: unicorn::Mode,
+        profiler: &Profiler<C>,
+        output_registers: Arc<Vec<Register<C>>>,
     ) -> Result<unicorn::uc_hook, unicorn::Error> {
         let pc: i32 = emu.program_counter().into();
+        let syscall_log = profiler.syscall_log.clone();
 
         let callback = move |engine: &unicorn::Unicorn<'_>, a| {
             // TODO log the errors
@@ -470,6 +496,21 @@ pub mod hooking {
                 if let Some(insts) = memory.disassemble(address, 64, Some(1)) {
                     if let Some(inst) = insts.iter().next() {
                         if is_syscall(arch, mode, &inst) {
+                            let registers = output_registers
+                                .iter()
+                                .map(|reg| {
+                                    let reg_i = (*reg).into();
+                                    let val =
+                                        engine.reg_read(reg_i).expect("Failed to read register");
+                                    (*reg, val)
+                                })
+                                .collect::<HashMap<Register<C>, u64>>();
+                            let written_memory =
+                                tools::read_written_memory(engine).unwrap_or_else(|e| {
+                                    log::error!("Failed to read memory at syscall: {:?}", e);
+                                    vec![]
+                                });
+                            syscall_log.push((registers, written_memory));
                             engine.emu_stop().expect("Failed to stop engine!");
                         }
                     }
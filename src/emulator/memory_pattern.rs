@@ -0,0 +1,173 @@
+use hashbrown::HashSet;
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::loader::Seg;
+use crate::emulator::profiler::MemLogEntry;
+use crate::emulator::register_pattern::Target;
+
+/// The "write this string somewhere in memory" task. The creature is
+/// asked to write `bytes` into writeable memory and, optionally, to
+/// leave `register` pointing at them.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MemoryPatternConfig {
+    /// Given in the same notation as the byte targets of a register
+    /// pattern, e.g. `"/bin/sh"` or `x"2f62696e2f736800"`.
+    pub bytes: String,
+    pub register: Option<String>,
+    /// If set, the register is only scored once the bytes have been
+    /// written in full.
+    #[serde(default)]
+    pub staged: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryPattern {
+    pub bytes: Vec<u8>,
+    pub register: Option<String>,
+    pub staged: bool,
+}
+
+impl From<&MemoryPatternConfig> for MemoryPattern {
+    fn from(mp: &MemoryPatternConfig) -> Self {
+        let bytes = match mp
+            .bytes
+            .parse::<Target>()
+            .expect("Failed to parse memory pattern")
+        {
+            Target::Bytes(bytes) => bytes,
+            t => panic!("Memory pattern must be a string of bytes, not {:?}", t),
+        };
+        Self {
+            bytes,
+            register: mp.register.clone(),
+            staged: mp.staged,
+        }
+    }
+}
+
+/// Where, and how much of, a `MemoryPattern` was found in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryMatch {
+    pub addr: u64,
+    pub len: usize,
+}
+
+impl MemoryMatch {
+    pub fn is_aligned(&self, word_size: usize) -> bool {
+        self.addr % word_size as u64 == 0
+    }
+}
+
+fn read_byte(addr: u64, memory: &[Seg]) -> Option<u8> {
+    memory
+        .iter()
+        .find(|s| s.aligned_start() <= addr && addr < s.aligned_end())
+        .and_then(|s| s.data.get((addr - s.aligned_start()) as usize))
+        .copied()
+}
+
+impl MemoryPattern {
+    /// The number of leading bytes of the pattern that can be read,
+    /// in order, from `memory`, beginning at `addr`.
+    pub fn match_len_at(&self, addr: u64, memory: &[Seg]) -> usize {
+        self.bytes
+            .iter()
+            .enumerate()
+            .take_while(|(i, b)| read_byte(addr + *i as u64, memory) == Some(**b))
+            .count()
+    }
+
+    /// Looks for the longest partial match of the pattern in `memory`,
+    /// which should hold the writeable memory as it stood at the end of
+    /// execution. Only matches that overlap one of the writes in the
+    /// `write_log` are considered, so that strings which were already
+    /// lying around in memory don't count. Ties are broken in favour of
+    /// word-aligned matches.
+    pub fn longest_match(
+        &self,
+        write_log: &[MemLogEntry],
+        memory: &[Seg],
+        word_size: usize,
+    ) -> Option<MemoryMatch> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let len = self.bytes.len() as u64;
+        let mut candidates = HashSet::new();
+        for entry in write_log {
+            let start = entry.address.saturating_sub(len - 1);
+            let end = entry.address + entry.num_bytes_written as u64;
+            candidates.extend(start..end);
+        }
+        candidates
+            .into_iter()
+            .map(|addr| MemoryMatch {
+                addr,
+                len: self.match_len_at(addr, memory),
+            })
+            .filter(|m| m.len > 0)
+            .max_by_key(|m| (m.len, m.is_aligned(word_size), std::cmp::Reverse(m.addr)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::loader::SegType;
+    use crate::util::architecture::Perms;
+
+    use super::*;
+
+    fn seg(addr: u64, data: &[u8]) -> Seg {
+        let mut data = data.to_vec();
+        data.resize(0x1000, 0);
+        Seg {
+            addr,
+            memsz: data.len(),
+            perm: Perms::READ | Perms::WRITE,
+            segtype: SegType::Load,
+            data,
+        }
+    }
+
+    fn write(address: u64, num_bytes_written: usize) -> MemLogEntry {
+        MemLogEntry {
+            program_counter: 0,
+            address,
+            num_bytes_written,
+            value: 0,
+        }
+    }
+
+    #[test]
+    fn test_longest_match() {
+        let pattern = MemoryPattern::from(&MemoryPatternConfig {
+            bytes: "\"/bin/sh\"".to_string(),
+            register: None,
+            staged: false,
+        });
+        let memory = vec![seg(0x1000, b"xx/bin/sh\0..../bin\0")];
+
+        // the write touched the complete string
+        let m = pattern.longest_match(&[write(0x1004, 4)], &memory, 4);
+        assert_eq!(
+            m,
+            Some(MemoryMatch {
+                addr: 0x1002,
+                len: 8
+            })
+        );
+        assert!(!m.unwrap().is_aligned(4));
+
+        // only the partial string was written to
+        let m = pattern.longest_match(&[write(0x100e, 4)], &memory, 4);
+        assert_eq!(
+            m,
+            Some(MemoryMatch {
+                addr: 0x100e,
+                len: 4
+            })
+        );
+
+        assert_eq!(pattern.longest_match(&[], &memory, 4), None);
+    }
+}
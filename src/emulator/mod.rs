@@ -1,5 +1,6 @@
 pub mod hatchery;
 pub mod loader;
+pub mod memory_pattern;
 pub mod pack;
 pub mod profiler;
 pub mod register_pattern;
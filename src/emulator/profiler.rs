@@ -12,9 +12,14 @@ use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use unicorn::Cpu;
 
+use crate::configure::Distance;
 use crate::emulator::loader;
 use crate::emulator::loader::{get_static_memory_image, Seg};
 use crate::emulator::register_pattern::{Register, RegisterState};
+use crate::evolution::sharing::chromosome_distance;
+use crate::evolution::{GeneRole, Genome};
+use crate::util::architecture::Perms;
+use crate::util::bitwise::ham_rat;
 
 // TODO: why store the size at all, if you're just going to
 // throw it away?
@@ -58,6 +63,9 @@ pub struct Profiler<C: Cpu<'static>> {
     pub block_log: Arc<SegQueue<Block>>,
     pub gadget_log: Arc<SegQueue<u64>>,
     //Arc<RwLock<Vec<u64>>>,
+    /// Snapshots of the output registers, taken whenever a syscall is reached,
+    /// along with the writeable memory that had been written to by then.
+    pub syscall_log: Arc<SegQueue<(HashMap<Register<C>, u64>, Vec<Seg>)>>,
     /// These fields are written to after the emulation has finished.
     pub written_memory: Vec<Seg>,
     pub write_log: Arc<SegQueue<MemLogEntry>>,
@@ -76,6 +84,12 @@ pub struct Profile {
     pub cpu_errors: HashMap<unicorn::Error, usize>,
     pub emulation_times: Vec<Duration>,
     pub registers: Vec<RegisterState>,
+    /// The register states at each syscall reached, for each input.
+    pub syscall_registers: Vec<Vec<RegisterState>>,
+    /// The memory written by each syscall reached, for each input, against
+    /// which the corresponding `syscall_registers` are dereferenced.
+    #[serde(skip)]
+    pub syscall_memory: Vec<Vec<Vec<Seg>>>,
     pub gadgets_executed: HashSet<u64>,
     #[cfg(not(feature = "full_dump"))]
     #[serde(skip)]
@@ -102,6 +116,8 @@ impl Profile {
             cpu_errors,
             emulation_times,
             registers,
+            syscall_registers,
+            syscall_memory,
             gadgets_executed,
             writeable_memory,
             write_logs,
@@ -112,6 +128,8 @@ impl Profile {
         self.cpu_errors.extend(cpu_errors.into_iter());
         self.emulation_times.extend(emulation_times.into_iter());
         self.registers.extend(registers.into_iter());
+        self.syscall_registers.extend(syscall_registers.into_iter());
+        self.syscall_memory.extend(syscall_memory.into_iter());
         self.gadgets_executed.extend(gadgets_executed.into_iter());
         self.writeable_memory.extend(writeable_memory.into_iter());
         self.write_logs.extend(write_logs.into_iter());
@@ -126,8 +144,10 @@ impl Profile {
         let mut cpu_errors = HashMap::new();
         let mut computation_times = Vec::new();
         let mut register_maps = Vec::new();
+        let mut syscall_register_maps = Vec::new();
+        let mut syscall_memory = Vec::new();
         let mut gadgets_executed = HashSet::new();
-        let writeable_memory_regions = Vec::new();
+        let mut writeable_memory_regions = Vec::new();
         let mut write_logs = Vec::new();
 
         for Profiler {
@@ -137,6 +157,7 @@ impl Profile {
             emulation_time,
             registers,
             gadget_log,
+            syscall_log,
             written_memory,
             ..
         } in profilers.into_iter()
@@ -152,6 +173,17 @@ impl Profile {
             computation_times.push(emulation_time);
             // FIXME: use a different data type for output states.
             register_maps.push(RegisterState::new::<C>(&registers, Some(&written_memory)));
+            // Each syscall snapshot is dereferenced against the memory as it
+            // stood at the syscall, rather than at the end of the run.
+            let (syscall_states, syscall_segs): (Vec<_>, Vec<_>) = segqueue_to_vec(syscall_log)
+                .into_iter()
+                .map(|(regs, segs)| (RegisterState::new::<C>(&regs, Some(&segs)), segs))
+                .unzip();
+            syscall_register_maps.push(syscall_states);
+            syscall_memory.push(syscall_segs);
+            // Hang onto the written memory, so that constraints on what the registers
+            // point to can be checked against it.
+            writeable_memory_regions.push(written_memory);
 
             write_logs.push(segqueue_to_vec(write_log));
         }
@@ -162,12 +194,39 @@ impl Profile {
             emulation_times: computation_times,
             gadgets_executed,
             registers: register_maps,
+            syscall_registers: syscall_register_maps,
+            syscall_memory,
             writeable_memory: writeable_memory_regions,
             write_logs,
             executable: true,
         }
     }
 
+    /// The register states snapshotted at each syscall reached on the given
+    /// input, followed by its final register state, each paired with the
+    /// memory that had been written to when it was taken.
+    pub fn register_states(
+        &self,
+        input: usize,
+    ) -> impl Iterator<Item = (&RegisterState, Option<&[Seg]>)> + '_ {
+        let syscall_memory = self.syscall_memory.get(input);
+        let syscall_states = self
+            .syscall_registers
+            .get(input)
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(move |(i, state)| {
+                let memory = syscall_memory.and_then(|m| m.get(i)).map(Vec::as_slice);
+                (state, memory)
+            });
+        let final_state = self.registers.get(input).map(|state| {
+            let memory = self.writeable_memory.get(input).map(Vec::as_slice);
+            (state, memory)
+        });
+        syscall_states.chain(final_state)
+    }
+
     pub fn avg_emulation_micros(&self) -> f64 {
         self.emulation_times.iter().sum::<Duration>().as_micros() as f64
             / self.emulation_times.len() as f64
@@ -210,6 +269,80 @@ impl Profile {
         bytes_written as f64 / size_of_writeable as f64
     }
 
+    /// The role each word of a payload played in its execution: whether it
+    /// was executed as a gadget, consumed as data by the gadgets before it,
+    /// or never reached. The words after the last executed gadget count as
+    /// consumed up to the next word that points into executable memory,
+    /// which is where execution is taken to have gone astray.
+    pub fn word_roles(&self, words: &[u64]) -> Vec<GeneRole> {
+        let memory = get_static_memory_image();
+        let mut executed = self.gadgets_executed.clone();
+        executed.extend(self.basic_block_path_iterator().flatten().map(|b| b.entry));
+        let mut roles = words
+            .iter()
+            .map(|w| {
+                if executed.contains(w) {
+                    GeneRole::Executed
+                } else {
+                    GeneRole::Unexecuted
+                }
+            })
+            .collect::<Vec<GeneRole>>();
+        let first = roles.iter().position(|r| *r == GeneRole::Executed);
+        let last = roles.iter().rposition(|r| *r == GeneRole::Executed);
+        if let (Some(first), Some(last)) = (first, last) {
+            let astray = (last + 1..words.len())
+                .find(|i| {
+                    memory
+                        .perm_of_addr(words[*i])
+                        .map_or(false, |p| p.intersects(Perms::EXEC))
+                })
+                .unwrap_or_else(|| words.len());
+            for role in roles[first..astray].iter_mut() {
+                if *role == GeneRole::Unexecuted {
+                    *role = GeneRole::Consumed;
+                }
+            }
+        }
+        roles
+    }
+
+    /// The Jaccard distance between the sets of gadgets executed by two
+    /// profiles.
+    pub fn gadget_distance(&self, other: &Self) -> f64 {
+        let union = self.gadgets_executed.union(&other.gadgets_executed).count();
+        if union == 0 {
+            return 0.0;
+        }
+        let shared = self
+            .gadgets_executed
+            .intersection(&other.gadgets_executed)
+            .count();
+        1.0 - shared as f64 / union as f64
+    }
+
+    /// The mean Hamming ratio between the final register values of two
+    /// profiles, on each input. A register missing from either side counts
+    /// as entirely different.
+    pub fn register_distance(&self, other: &Self) -> f64 {
+        let distances = self
+            .registers
+            .iter()
+            .zip(other.registers.iter())
+            .flat_map(|(a, b)| {
+                a.0.iter().map(move |(reg, vals)| {
+                    b.0.get(reg)
+                        .map_or(1.0, |other_vals| ham_rat(vals[0], other_vals[0]))
+                })
+            })
+            .collect::<Vec<f64>>();
+        if distances.is_empty() {
+            0.0
+        } else {
+            distances.iter().sum::<f64>() / distances.len() as f64
+        }
+    }
+
     /// Given a word `w`, return a vector of entries that show
     /// that word was written. If the word was not written, return
     /// an empty vector.
@@ -290,6 +423,7 @@ impl<C: Cpu<'static>> Default for Profiler<C> {
             emulation_time: Duration::default(),
             block_log: Arc::new(SegQueue::new()),
             gadget_log: Arc::new(SegQueue::new()), //Arc::new(RwLock::new(Vec::new())),
+            syscall_log: Arc::new(SegQueue::new()),
             written_memory: vec![],
         }
     }
@@ -297,11 +431,34 @@ impl<C: Cpu<'static>> Default for Profiler<C> {
 
 pub trait HasProfile {
     fn profile(&self) -> Option<&Profile>;
+
+    /// The distance between two creatures, by the given measure. The
+    /// behavioural measures go by way of the creatures' profiles, and
+    /// creatures without profiles are as far apart as can be.
+    fn profile_distance(&self, other: &Self, measure: Distance) -> f64
+    where
+        Self: Genome,
+    {
+        match measure {
+            Distance::Chromosome => chromosome_distance(self.chromosome(), other.chromosome()),
+            Distance::Gadgets => match (self.profile(), other.profile()) {
+                (Some(a), Some(b)) => a.gadget_distance(b),
+                _ => 1.0,
+            },
+            Distance::Registers => match (self.profile(), other.profile()) {
+                (Some(a), Some(b)) => a.register_distance(b),
+                _ => 1.0,
+            },
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use unicorn::CpuX86;
+    use unicorn::{CpuX86, RegisterX86};
+
+    use crate::emulator::loader::SegType;
+    use crate::hashmap;
 
     use super::*;
 
@@ -348,4 +505,35 @@ mod test {
             std::mem::size_of_val(&profile.paths)
         );
     }
+
+    #[test]
+    fn test_syscall_snapshots_keep_their_memory() {
+        let seg = |data: Vec<u8>| Seg {
+            addr: 0x1000,
+            memsz: data.len(),
+            perm: Perms::READ | Perms::WRITE,
+            segtype: SegType::Load,
+            data,
+        };
+        let at_syscall = vec![seg(b"/bin/sh\0".to_vec())];
+        let at_end = vec![seg(vec![0; 8])];
+        let registers = hashmap! { RegisterX86::RDI => 0x1000 };
+        let profilers: Vec<Profiler<CpuX86<'_>>> = vec![Profiler {
+            syscall_log: Arc::new(segqueue![(registers.clone(), at_syscall.clone()),]),
+            registers,
+            written_memory: at_end.clone(),
+            ..Default::default()
+        }];
+
+        let profile: Profile = profilers.into();
+        let memories = profile
+            .register_states(0)
+            .map(|(_, memory)| memory)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            memories,
+            vec![Some(at_syscall.as_slice()), Some(at_end.as_slice())]
+        );
+        assert_eq!(profile.register_states(1).count(), 0);
+    }
 }
@@ -14,8 +14,7 @@ use crate::emulator::loader::{get_static_memory_image, Seg};
 use crate::emulator::profiler::Profile;
 use crate::error::Error;
 use crate::util;
-use crate::util::architecture::Endian;
-use crate::util::bitwise;
+use crate::util::architecture::{read_integer, write_integer};
 use crate::util::bitwise::nybble;
 
 pub type Register<C> = <C as Cpu<'static>>::Reg;
@@ -25,68 +24,179 @@ pub type Register<C> = <C as Cpu<'static>>::Reg;
 // - it contains the head or tail of the target (so that sliding it along may find the target)
 // - it points to writeable address. then look at hamming distance.
 
+/// The constraint that a `RegisterValue` places on the word found at
+/// the end of its chain of dereferences.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// Exactly this word.
+    Word(u64),
+    /// Any value at all.
+    Any,
+    /// Any value in the inclusive range `[lo, hi]`.
+    Range(u64, u64),
+    /// Any value that agrees with `val` on the bits set in `mask`.
+    Masked { val: u64, mask: u64 },
+    /// Any pointer into writeable memory.
+    Writeable,
+    /// A sequence of bytes, of any length. When dereferenced, these are
+    /// compared against the memory the word in the chain points to; when
+    /// immediate, against the word itself, laid out in the target's
+    /// endianness.
+    Bytes(Vec<u8>),
+    /// The same value as the one held in the named register.
+    Register(String),
+}
+
+impl Target {
+    /// Returns the word this target requires, if it requires exactly one.
+    pub fn as_word(&self) -> Option<u64> {
+        match self {
+            Target::Word(w) => Some(*w),
+            _ => None,
+        }
+    }
+
+    /// Returns the first word of the target, as it would be laid out in
+    /// memory by the target architecture. For byte targets that are
+    /// shorter than a word, the remainder is padded with zeroes.
+    pub fn leading_word(&self) -> Option<u64> {
+        match self {
+            Target::Word(w) => Some(*w),
+            Target::Bytes(b) => {
+                let memory = get_static_memory_image();
+                let mut bytes = b.clone();
+                bytes.resize(bytes.len().max(memory.word_size), 0);
+                read_integer(&bytes, memory.endian, memory.word_size)
+            }
+            _ => None,
+        }
+    }
+
+    /// Byte targets are matched against the memory that a word in the
+    /// chain points to, rather than against the word itself, so they sit
+    /// one step further along the chain than the word being inspected.
+    fn pointer_offset(&self) -> usize {
+        match self {
+            Target::Bytes(_) => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Parses a buffer written as pairs of hex digits, like `2f62696e2f7368`.
+fn parse_hex_buffer(s: &str) -> Result<Vec<u8>, Error> {
+    let s = s.trim();
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return Err(Error::Parsing(format!("Invalid hex buffer: {}", s)));
+    }
+    let bytes = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..(i + 2)], 16))
+        .collect::<Result<Vec<u8>, _>>()?;
+    Ok(bytes)
+}
+
+fn parse_numeral(s: &str) -> Result<u64, Error> {
+    let s = s.trim();
+    let val = if s.starts_with("0x") {
+        u64::from_str_radix(s.trim_start_matches("0x"), 16)?
+    } else {
+        u64::from_str_radix(s, 10)?
+    };
+    Ok(val)
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            Err(Error::Parsing("Invalid register value".into()))
+        } else if s == "*" {
+            Ok(Target::Any)
+        } else if s == "writeable" {
+            Ok(Target::Writeable)
+        } else if s.starts_with('=') {
+            Ok(Target::Register(
+                s.trim_start_matches('=').trim().to_string(),
+            ))
+        } else if s.starts_with('"') {
+            // C strings carry their NUL terminator with them
+            let chars = s.trim_start_matches('"').trim_end_matches('"');
+            let mut bytes = chars.as_bytes().to_vec();
+            bytes.push(0);
+            Ok(Target::Bytes(bytes))
+        } else if s.starts_with('\'') {
+            Ok(Target::Bytes(s[1..].as_bytes().to_vec()))
+        } else if s.starts_with("x\"") {
+            let hex = s.trim_start_matches('x').trim_matches('"');
+            Ok(Target::Bytes(parse_hex_buffer(hex)?))
+        } else if s.starts_with('[') {
+            let bounds = s
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split(',')
+                .map(parse_numeral)
+                .collect::<Result<Vec<u64>, Error>>()?;
+            match bounds.as_slice() {
+                [lo, hi] if lo <= hi => Ok(Target::Range(*lo, *hi)),
+                _ => Err(Error::Parsing(format!("Invalid range: {}", s))),
+            }
+        } else if let Some(i) = s.find('/') {
+            let val = parse_numeral(&s[..i])?;
+            let mask = parse_numeral(&s[(i + 1)..])?;
+            Ok(Target::Masked { val, mask })
+        } else {
+            Ok(Target::Word(parse_numeral(s)?))
+        }
+    }
+}
+
 /// For example, if EAX <- 0xdeadbeef, then EAX holds
-/// `RegisterValue { val: 0xdeadbeef, deref: 0 }`.
+/// `RegisterValue { val: Target::Word(0xdeadbeef), deref: 0 }`.
 /// But if EAX <- 0x12345678 <- 0xdeadbeef, then we have
-/// `RegisterValue { val: 0xdeadbeef, deref: 1 }`
+/// `RegisterValue { val: Target::Word(0xdeadbeef), deref: 1 }`
 /// and so on.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct RegisterValue {
-    pub val: u64,
+    pub val: Target,
     deref: usize,
 }
 
 impl From<u64> for RegisterValue {
     fn from(val: u64) -> Self {
-        Self { val, deref: 0 }
+        Self {
+            val: Target::Word(val),
+            deref: 0,
+        }
     }
 }
 
 /// Grammar:
 /// ```
-/// RegisterValue -> numeric_literal | & RegisterValue
+/// RegisterValue -> & RegisterValue
+///                | *                     (any value)
+///                | [ numeral , numeral ] (any value in the inclusive range)
+///                | numeral / numeral     (a value, compared under a bitmask)
+///                | = register            (the value held in another register)
+///                | writeable             (any pointer into writeable memory)
+///                | "chars"               (a NUL-terminated string)
+///                | 'chars                (a string, without the terminator)
+///                | x"hex digits"         (a buffer of bytes, e.g. x"2f62696e")
+///                | numeral
 /// ```
 impl FromStr for RegisterValue {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        fn parse_rv<I: Iterator<Item = char>>(
-            mut chars: I,
-            deref: usize,
-        ) -> Result<RegisterValue, Error> {
-            let ch = chars.next();
-            match ch {
-                None => Err(Error::Parsing("Invalid register value".into())),
-                Some('&') => parse_rv(chars, deref + 1),
-                Some(' ') => parse_rv(chars, deref),
-                // You could handle short strings here, too.
-                // Match on an opening single quote, and pack `word_size` bytes into an
-                // integer. TODO
-                Some('\'') => {
-                    let s = chars.collect::<String>();
-                    // FIXME: don't hardcode the endian
-                    if let Some(w) = bitwise::try_str_as_word(s, Endian::Little) {
-                        Ok(RegisterValue { val: w, deref })
-                    } else {
-                        Err(Error::Parsing(
-                            "can only encode strings of fewer than 8 characters".into(),
-                        ))
-                    }
-                }
-                Some(x) => {
-                    let rest = chars.collect::<String>();
-                    let numeral = format!("{}{}", x, rest);
-                    let val = if numeral.starts_with("0x") {
-                        let numeral = numeral.trim_start_matches("0x");
-                        u64::from_str_radix(&numeral, 16)?
-                    } else {
-                        u64::from_str_radix(&numeral, 10)?
-                    };
-                    Ok(RegisterValue { val, deref })
-                }
-            }
-        }
-        parse_rv(s.chars(), 0)
+        let target = s.trim_start_matches(|c: char| c == '&' || c == ' ');
+        let deref = s[..(s.len() - target.len())]
+            .chars()
+            .filter(|c| *c == '&')
+            .count();
+        let val = target.parse::<Target>()?;
+        Ok(RegisterValue { val, deref })
     }
 }
 
@@ -155,7 +265,7 @@ impl<C: 'static + Cpu<'static>> TryFrom<&RegisterPattern> for UnicornRegisterSta
             let reg = k
                 .parse()
                 .map_err(|_| Self::Error::Parsing("Failed to parse register string".to_string()))?;
-            map.insert(reg, v.val);
+            map.insert(reg, v.val.as_word().unwrap_or(0));
         }
         Ok(UnicornRegisterState(map))
     }
@@ -187,9 +297,81 @@ fn max_word_distance() -> f64 {
     get_static_memory_image().word_size as f64 * 8.0
 }
 
+/// Distances outside of the target range are measured on a logarithmic
+/// scale, so that they're commensurable with the hamming distances used
+/// elsewhere.
+fn range_distance(w: u64, lo: u64, hi: u64) -> f64 {
+    let gap = if w < lo {
+        lo - w
+    } else if w > hi {
+        w - hi
+    } else {
+        0
+    };
+    (gap as f64 + 1.0).log2()
+}
+
+/// Measures how far the address `addr` falls from the nearest segment of
+/// writeable memory, on the same logarithmic scale as `range_distance`.
+fn writeable_distance(addr: u64, extra_segs: Option<&[Seg]>) -> f64 {
+    let memory = get_static_memory_image();
+    memory
+        .segments()
+        .iter()
+        .chain(extra_segs.into_iter().flatten())
+        .filter(|s| s.is_writeable())
+        .map(|s| range_distance(addr, s.aligned_start(), s.aligned_end() - 1))
+        .fold1(|a, b| a.min(b))
+        .unwrap_or_else(max_word_distance)
+}
+
+/// Compares `target` against `bytes`, bit by bit. Any part of the target
+/// that runs past the end of `bytes` counts as getting every bit wrong.
+fn bytes_distance(bytes: &[u8], target: &[u8]) -> f64 {
+    target
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            bytes
+                .get(i)
+                .map(|b| (b ^ t).count_ones() as f64)
+                .unwrap_or(8.0)
+        })
+        .sum()
+}
+
+/// Compares the immediate word `w`, laid out in memory as the target
+/// architecture would lay it out, against the bytes of `target`.
+fn word_bytes_distance(w: u64, target: &[u8]) -> f64 {
+    let memory = get_static_memory_image();
+    let word_size = memory.word_size;
+    let mut bytes = vec![0_u8; word_size];
+    write_integer(memory.endian, word_size, w, &mut bytes);
+    bytes_distance(&bytes, target)
+}
+
+/// Compares `target` against the region of memory beginning at `addr`,
+/// which may extend into the `extra_segs` written during execution.
+/// Unmapped addresses are treated as getting every bit wrong, plus their
+/// distance from writeable memory.
+fn pointee_distance(addr: u64, target: &[u8], extra_segs: Option<&[Seg]>) -> f64 {
+    let memory = get_static_memory_image();
+    if let Some(bytes) = memory.try_dereference(addr, extra_segs) {
+        bytes_distance(bytes, target)
+    } else {
+        8.0 * target.len() as f64 + writeable_distance(addr, extra_segs)
+    }
+}
+
 // TODO: write some integration tests for this. there's a LOT of room for error!
 impl RegisterPattern {
-    pub fn distance_from_register_state(&self, register_state: &RegisterState) -> f64 {
+    /// The `extra_segs` are consulted, along with the static memory image,
+    /// whenever a constraint needs to look at the memory a register points to.
+    pub fn distance_from_register_state(
+        &self,
+        register_state: &RegisterState,
+        extra_segs: Option<&[Seg]>,
+    ) -> f64 {
         const WRONG_REG_PENALTY: f64 = 5.0;
 
         let summed_dist = self
@@ -200,7 +382,7 @@ impl RegisterPattern {
                     .keys()
                     .map(|r| {
                         let mut d = register_state
-                            .distance_from_register_val(r, r_val)
+                            .distance_from_register_val(r, r_val, extra_segs)
                             .expect("Failed to get distance from register val");
                         log::debug!("[{}] summed_dist_for_reg({}, {:x?}) = {}", reg, r, r_val, d);
                         if r != reg {
@@ -223,12 +405,13 @@ impl RegisterPattern {
     pub fn incorrect_register_states<'a>(
         &'a self,
         register_state: &'a RegisterState,
+        extra_segs: Option<&[Seg]>,
     ) -> Vec<(&'a String, &'a Vec<u64>)> {
         self.0
             .iter()
             .filter_map(|(reg, r_val)| {
                 let d = register_state
-                    .distance_from_register_val(reg, r_val)
+                    .distance_from_register_val(reg, r_val, extra_segs)
                     .expect("Failed to get distance from register val");
                 if d > 0.0 {
                     register_state.0.get(reg).map(|v| (reg, v))
@@ -246,8 +429,10 @@ impl RegisterPattern {
     ) -> usize {
         self.0
             .values()
-            .filter(|v| v.deref > 0 && (!exclude_null || v.val != 0))
-            .map(|v| profile.was_this_written(v.val).len())
+            .filter(|v| v.deref > 0)
+            .filter_map(|v| v.val.leading_word())
+            .filter(|w| !exclude_null || *w != 0)
+            .map(|w| profile.was_this_written(w).len())
             .sum()
     }
 
@@ -255,15 +440,58 @@ impl RegisterPattern {
         let mut map = HashMap::new();
         let memory = loader::get_static_memory_image();
         for (k, v) in self.0.iter() {
-            let path = memory.deref_chain(v.val, 10, extra_segs);
-            map.insert(k.to_string(), path);
+            if let Some(w) = v.val.as_word() {
+                let path = memory.deref_chain(w, 10, extra_segs);
+                map.insert(k.to_string(), path);
+            }
         }
         map
     }
 
+    /// Returns the names of every register that the pattern mentions,
+    /// including those whose values are only referred to by other
+    /// registers' targets. These all need to be read from the emulator.
+    pub fn registers(&self) -> Vec<&str> {
+        let mut regs = Vec::new();
+        for (k, v) in self.0.iter() {
+            regs.push(k.as_str());
+            if let Target::Register(ref r) = v.val {
+                regs.push(r.as_str());
+            }
+        }
+        regs.sort();
+        regs.dedup();
+        regs
+    }
+
+    /// Returns a copy of the pattern that only constrains the given registers.
+    pub fn restricted_to(&self, registers: &[String]) -> Self {
+        Self(
+            self.0
+                .iter()
+                .filter(|(k, _)| registers.contains(k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        )
+    }
+
     pub fn features(&self) -> Vec<RegisterFeature> {
         RegisterFeature::decompose_reg_pattern(self)
     }
+
+    /// Breaks the pattern into separate fitness cases, one for each
+    /// feature of the pattern on each of the `num_inputs` input states.
+    pub fn cases(&self, num_inputs: usize) -> Vec<RegisterCase> {
+        let features = self.features();
+        (0..num_inputs)
+            .flat_map(|input| {
+                features
+                    .iter()
+                    .cloned()
+                    .map(move |feature| RegisterCase { input, feature })
+            })
+            .collect()
+    }
 }
 
 impl From<&RegisterPattern> for Vec<u8> {
@@ -273,7 +501,7 @@ impl From<&RegisterPattern> for Vec<u8> {
         let mut buf = vec![0_u8; len * WORD_SIZE];
         let mut offset = 0;
         for word in rp.0.values() {
-            LittleEndian::write_u64(&mut buf[offset..], word.val);
+            LittleEndian::write_u64(&mut buf[offset..], word.val.as_word().unwrap_or(0));
             offset += WORD_SIZE;
         }
         buf
@@ -281,25 +509,54 @@ impl From<&RegisterPattern> for Vec<u8> {
 }
 
 #[derive(Clone, Debug, Hash)]
-pub struct RegisterFeature {
-    register: String,
-    index: usize,
-    deref: usize,
-    nybble: u8,
+pub enum RegisterFeature {
+    /// One nybble of a target that pins down particular bits of a word.
+    Nybble {
+        register: String,
+        index: usize,
+        deref: usize,
+        nybble: u8,
+        mask: u8,
+    },
+    /// A target that can't be broken into nybbles, checked as a whole.
+    Whole {
+        register: String,
+        value: RegisterValue,
+    },
 }
 
 impl RegisterFeature {
+    /// Only targets that pin down particular bits of a word can be broken
+    /// into nybble features. Masked targets yield a feature for each nybble
+    /// that the mask touches, and every other target yields a single
+    /// feature for the whole register.
     fn decompose_reg_val(register: &str, reg_val: &RegisterValue, reg_feats: &mut Vec<Self>) {
         let word_size = get_static_memory_image().word_size;
+        let (val, mask) = match reg_val.val {
+            Target::Word(w) => (w, !0_u64),
+            Target::Masked { val, mask } => (val, mask),
+            _ => {
+                reg_feats.push(Self::Whole {
+                    register: register.to_string(),
+                    value: reg_val.clone(),
+                });
+                return;
+            }
+        };
 
         for i in 0..(word_size * 2) {
-            let nybble = nybble(reg_val.val, i);
+            let nybble_mask = nybble(mask, i);
+            if nybble_mask == 0 {
+                continue;
+            }
+            let nybble = nybble(val, i) & nybble_mask;
             let deref = reg_val.deref;
-            reg_feats.push(Self {
+            reg_feats.push(Self::Nybble {
                 register: register.to_string(),
                 index: i,
                 deref,
                 nybble,
+                mask: nybble_mask,
             })
         }
     }
@@ -312,21 +569,59 @@ impl RegisterFeature {
         reg_feats
     }
 
-    pub fn check_state(&self, state: &RegisterState) -> bool {
-        if let Some(deref_chain) = state.0.get(&self.register) {
-            if self.deref >= deref_chain.len() {
-                false
-            } else {
-                let val = deref_chain[self.deref];
-                let nyb = nybble(val, self.index);
-                nyb == self.nybble
-            }
-        } else {
-            false
+    pub fn check_state(&self, state: &RegisterState, extra_segs: Option<&[Seg]>) -> bool {
+        self.error(state, extra_segs) == 0.0
+    }
+
+    /// For nybble features, the fraction of the feature's masked bits that
+    /// are wrong in `state`. Whole-register features squash the distance
+    /// measured by the `register_pattern` fitness function into the same
+    /// range. A register that is missing, or can't be dereferenced far
+    /// enough, gets the maximum error of 1.0.
+    pub fn error(&self, state: &RegisterState, extra_segs: Option<&[Seg]>) -> f64 {
+        match self {
+            Self::Nybble {
+                register,
+                index,
+                deref,
+                nybble: target,
+                mask,
+            } => match state.0.get(register) {
+                Some(deref_chain) if *deref < deref_chain.len() => {
+                    let val = deref_chain[*deref];
+                    let nyb = nybble(val, *index) & mask;
+                    let wrong = (nyb ^ target).count_ones();
+                    wrong as f64 / mask.count_ones() as f64
+                }
+                _ => 1.0,
+            },
+            Self::Whole { register, value } => state
+                .distance_from_register_val(register, value, extra_segs)
+                .map_or(1.0, |d| d / (d + 1.0)),
         }
     }
 }
 
+/// A single lexicase fitness case: one `RegisterFeature`, checked against
+/// the register states produced by one of the inputs.
+#[derive(Clone, Debug, Hash)]
+pub struct RegisterCase {
+    pub input: usize,
+    pub feature: RegisterFeature,
+}
+
+impl RegisterCase {
+    /// As with the `register_pattern` fitness function, the states
+    /// snapshotted at each syscall are considered alongside the final
+    /// state, and the best of them is taken.
+    pub fn error(&self, profile: &Profile) -> f64 {
+        profile
+            .register_states(self.input)
+            .map(|(state, memory)| self.feature.error(state, memory))
+            .fold(1.0, f64::min)
+    }
+}
+
 /// The `RegisterState` represents the state of the emulator's CPU
 /// at the end of execution, seen from the perspective of a subset
 /// of registers and their referential chains in memory.
@@ -382,7 +677,37 @@ impl RegisterState {
         map
     }
 
-    fn distance_from_register_val(&self, reg: &str, r_val: &RegisterValue) -> Result<f64, Error> {
+    /// Measures the distance between the word at position `i` of the chain `vals`
+    /// and the `target`, ignoring its position in the chain.
+    fn target_distance(
+        &self,
+        target: &Target,
+        i: usize,
+        vals: &[u64],
+        extra_segs: Option<&[Seg]>,
+    ) -> Result<f64, Error> {
+        let w = vals[i];
+        let distance = match target {
+            Target::Word(t) => word_distance(w, *t),
+            Target::Any => 0.0,
+            Target::Range(lo, hi) => range_distance(w, *lo, *hi),
+            Target::Masked { val, mask } => word_distance(w & mask, val & mask),
+            Target::Writeable => writeable_distance(w, extra_segs),
+            Target::Bytes(b) => pointee_distance(w, b, extra_segs),
+            Target::Register(r) => {
+                let other = self.0.get(r).ok_or_else(|| Error::MissingKey(r.to_string()))?;
+                word_distance(w, other[0])
+            }
+        };
+        Ok(distance)
+    }
+
+    fn distance_from_register_val(
+        &self,
+        reg: &str,
+        r_val: &RegisterValue,
+        extra_segs: Option<&[Seg]>,
+    ) -> Result<f64, Error> {
         fn pos_distance(pos: usize, target: usize) -> f64 {
             let pos_dist_scale: f64 = 4.0 * get_static_memory_image().word_size as f64;
             let dist = pos as i32 - target as i32;
@@ -405,22 +730,27 @@ impl RegisterState {
 
         log::debug!("want {:x?}", r_val);
         if let Some(vals) = self.0.get(reg) {
-            let distance = if r_val.deref == 0 {
+            let distance = if let (0, Target::Bytes(b)) = (r_val.deref, &r_val.val) {
+                // Immediate strings are packed into the register itself
+                word_bytes_distance(vals[0], b)
+            } else if r_val.deref == 0 {
                 // Immediate values
-                word_distance(vals[0], r_val.val)
+                self.target_distance(&r_val.val, 0, vals, extra_segs)?
             } else {
                 // dereferenced values
-                vals.iter()
-                    .enumerate()
-                    .map(|(i, val)| {
-                        if is_mutable(i, &vals) {
-                            word_distance(*val, r_val.val) + pos_distance(i, r_val.deref)
-                        } else if *val == r_val.val {
-                            0.0 + pos_distance(i, r_val.deref)
+                let offset = r_val.val.pointer_offset();
+                (0..vals.len())
+                    .map(|i| {
+                        let d = self.target_distance(&r_val.val, i, vals, extra_segs)?;
+                        let pos = i + offset;
+                        if is_mutable(pos, &vals) {
+                            Ok(d + pos_distance(pos, r_val.deref))
                         } else {
-                            2.0 * word_distance(*val, r_val.val) + pos_distance(i, r_val.deref)
+                            Ok(2.0 * d + pos_distance(pos, r_val.deref))
                         }
                     })
+                    .collect::<Result<Vec<f64>, Error>>()?
+                    .into_iter()
                     .fold1(|a, b| a.min(b))
                     .unwrap()
             };
@@ -472,7 +802,7 @@ mod test {
 
     use crate::configure::RoperConfig;
     use crate::emulator::register_pattern::{RegisterPattern, RegisterValue};
-    use crate::hashmap;
+    use crate::{assert_close_f64, hashmap};
 
     use super::*;
 
@@ -514,22 +844,28 @@ mod test {
             (
                 "0xdeadbeef",
                 RegisterValue {
-                    val: 0xdead_beef,
+                    val: Target::Word(0xdead_beef),
                     deref: 0,
                 },
             ),
             (
                 "&0xbeef",
                 RegisterValue {
-                    val: 0xbeef,
+                    val: Target::Word(0xbeef),
                     deref: 1,
                 },
             ),
-            ("&&0", RegisterValue { val: 0, deref: 2 }),
+            (
+                "&&0",
+                RegisterValue {
+                    val: Target::Word(0),
+                    deref: 2,
+                },
+            ),
             (
                 "& & & & 1234",
                 RegisterValue {
-                    val: 1234,
+                    val: Target::Word(1234),
                     deref: 4,
                 },
             ),
@@ -552,17 +888,17 @@ mod test {
 
         let register_pattern = RegisterPattern(hashmap! {
             "RAX".to_string() => RegisterValue {
-                val: 0xbeef,
+                val: Target::Word(0xbeef),
                 deref: 1,
             },
 
             "RBX".to_string() => RegisterValue {
-                val: 3,
+                val: Target::Word(3),
                 deref: 2,
             },
         });
 
-        let res = register_pattern.distance_from_register_state(&register_state);
+        let res = register_pattern.distance_from_register_state(&register_state, None);
 
         assert!(res < std::f64::EPSILON, "nonzero score on match");
     }
@@ -572,12 +908,12 @@ mod test {
         initialize_mem_image();
         let register_pattern = RegisterPattern(hashmap! {
             "RAX".to_string() => RegisterValue {
-                val: 0xbeef,
+                val: Target::Word(0xbeef),
                 deref: 1,
             },
 
             "RBX".to_string() => RegisterValue {
-                val: 3,
+                val: Target::Word(3),
                 deref: 2,
             },
         });
@@ -596,7 +932,7 @@ mod test {
 
         let res = features
             .iter()
-            .all(|feat| feat.check_state(&register_state));
+            .all(|feat| feat.check_state(&register_state, None));
         assert!(res);
 
         let register_state = RegisterState(hashmap! {
@@ -605,8 +941,73 @@ mod test {
         });
         let res = features
             .iter()
-            .all(|feat| feat.check_state(&register_state));
+            .all(|feat| feat.check_state(&register_state, None));
         assert!(!res);
+
+        // 0xf vs 0x8 and 0x3 vs 0x4 each get three of four bits wrong
+        let worst = features
+            .iter()
+            .map(|feat| feat.error(&register_state, None))
+            .fold(0.0, f64::max);
+        assert!((worst - 0.75).abs() < std::f64::EPSILON);
+
+        assert_eq!(register_pattern.cases(2).len(), features.len() * 2);
+    }
+
+    #[test]
+    fn test_whole_register_features() {
+        initialize_mem_image();
+        let register_pattern = RegisterPattern(hashmap! {
+            "RCX".to_string() => RegisterValue {
+                val: Target::Range(10, 20),
+                deref: 0,
+            },
+        });
+        let features = register_pattern.features();
+        assert_eq!(features.len(), 1);
+        let feature = &features[0];
+
+        let register_state = RegisterState(hashmap! { "RCX".to_string() => vec![15] });
+        assert!(feature.check_state(&register_state, None));
+
+        let register_state = RegisterState(hashmap! { "RCX".to_string() => vec![100] });
+        let error = feature.error(&register_state, None);
+        assert!(error > 0.0 && error < 1.0);
+
+        let register_state = RegisterState(hashmap! { "RAX".to_string() => vec![15] });
+        assert_close_f64!(feature.error(&register_state, None), 1.0);
+    }
+
+    #[test]
+    fn test_register_case_takes_best_snapshot() {
+        initialize_mem_image();
+        let register_pattern = RegisterPattern(hashmap! {
+            "RCX".to_string() => RegisterValue {
+                val: Target::Range(10, 20),
+                deref: 0,
+            },
+        });
+        let case = register_pattern.cases(1).pop().expect("no register cases");
+
+        let mut profile = Profile {
+            registers: vec![RegisterState(hashmap! { "RCX".to_string() => vec![100] })],
+            ..Default::default()
+        };
+        let final_error = case.error(&profile);
+        assert!(final_error > 0.0);
+
+        // a syscall reached with the right value set up is enough,
+        // whatever happens to RCX afterwards
+        profile.syscall_registers = vec![vec![
+            RegisterState(hashmap! { "RCX".to_string() => vec![1000] }),
+            RegisterState(hashmap! { "RCX".to_string() => vec![15] }),
+        ]];
+        assert_close_f64!(case.error(&profile), 0.0);
+
+        profile.syscall_registers = vec![vec![RegisterState(
+            hashmap! { "RCX".to_string() => vec![1000] },
+        )]];
+        assert_close_f64!(case.error(&profile), final_error);
     }
 
     #[test]
@@ -621,15 +1022,23 @@ mod test {
             .distance_from_register_val(
                 "RAX",
                 &RegisterValue {
-                    val: 0xbeef,
+                    val: Target::Word(0xbeef),
                     deref: 1,
                 },
+                None,
             )
             .unwrap();
         assert!(res < std::f64::EPSILON, "Match failed");
 
         let res = register_state
-            .distance_from_register_val("RBX", &RegisterValue { val: 3, deref: 2 })
+            .distance_from_register_val(
+                "RBX",
+                &RegisterValue {
+                    val: Target::Word(3),
+                    deref: 2,
+                },
+                None,
+            )
             .unwrap();
         assert!(res < std::f64::EPSILON, "Match failed");
 
@@ -637,9 +1046,10 @@ mod test {
             .distance_from_register_val(
                 "RAX",
                 &RegisterValue {
-                    val: 0x1000_beef,
+                    val: Target::Word(0x1000_beef),
                     deref: 1,
                 },
+                None,
             )
             .unwrap();
         assert!(res - 1.0 < std::f64::EPSILON, "Match failed");
@@ -648,9 +1058,10 @@ mod test {
             .distance_from_register_val(
                 "RAX",
                 &RegisterValue {
-                    val: 0x1000_beef,
+                    val: Target::Word(0x1000_beef),
                     deref: 0,
                 },
+                None,
             )
             .unwrap();
         assert!(res - 2.0 < std::f64::EPSILON, "Match failed");
@@ -661,9 +1072,121 @@ mod test {
             "RAX".to_string() => vec![1, 7],
         });
         let res = register_state
-            .distance_from_register_val("RAX", &RegisterValue { val: 9, deref: 3 })
+            .distance_from_register_val(
+                "RAX",
+                &RegisterValue {
+                    val: Target::Word(9),
+                    deref: 3,
+                },
+                None,
+            )
             .unwrap();
         println!("res = {}", res);
         assert!(res - (1.0 + 3.0) < std::f64::EPSILON);
     }
+
+    #[test]
+    fn test_register_value_grammar() {
+        let rvs = vec![
+            (
+                "*",
+                RegisterValue {
+                    val: Target::Any,
+                    deref: 0,
+                },
+            ),
+            (
+                "&[0x10, 0x20]",
+                RegisterValue {
+                    val: Target::Range(0x10, 0x20),
+                    deref: 1,
+                },
+            ),
+            (
+                "0x3b/0xff",
+                RegisterValue {
+                    val: Target::Masked {
+                        val: 0x3b,
+                        mask: 0xff,
+                    },
+                    deref: 0,
+                },
+            ),
+            (
+                "writeable",
+                RegisterValue {
+                    val: Target::Writeable,
+                    deref: 0,
+                },
+            ),
+            (
+                "&\"/bin/sh\"",
+                RegisterValue {
+                    val: Target::Bytes(b"/bin/sh\0".to_vec()),
+                    deref: 1,
+                },
+            ),
+            (
+                "=EBX",
+                RegisterValue {
+                    val: Target::Register("EBX".to_string()),
+                    deref: 0,
+                },
+            ),
+            (
+                "&'/bin/sh",
+                RegisterValue {
+                    val: Target::Bytes(b"/bin/sh".to_vec()),
+                    deref: 1,
+                },
+            ),
+            (
+                "&x\"2f62696e2f736800\"",
+                RegisterValue {
+                    val: Target::Bytes(b"/bin/sh\0".to_vec()),
+                    deref: 1,
+                },
+            ),
+        ];
+
+        for (s, reg_val) in rvs.into_iter() {
+            let rv: RegisterValue = s.parse().expect("Failed to parse");
+            assert_eq!(rv, reg_val);
+        }
+
+        assert!("x\"2f6\"".parse::<RegisterValue>().is_err());
+        assert!("[0x20, 0x10]".parse::<RegisterValue>().is_err());
+    }
+
+    #[test]
+    fn test_constraint_distances() {
+        let register_state = RegisterState(hashmap! {
+            "RAX".to_string() => vec![0x1_003b],
+            "RBX".to_string() => vec![0x15],
+            "RCX".to_string() => vec![0x15],
+        });
+
+        let dist = |reg: &str, s: &str| {
+            register_state
+                .distance_from_register_val(reg, &s.parse().unwrap(), None)
+                .unwrap()
+        };
+
+        assert_close_f64!(dist("RAX", "*"), 0.0);
+        assert_close_f64!(dist("RAX", "0x3b/0xff"), 0.0);
+        assert_close_f64!(dist("RAX", "0x3a/0xff"), 1.0);
+        assert_close_f64!(dist("RBX", "[0x10, 0x20]"), 0.0);
+        assert_close_f64!(dist("RBX", "[0x16, 0x20]"), 1.0);
+        assert!(dist("RAX", "[0x16, 0x20]") > dist("RBX", "[0x16, 0x20]"));
+        assert_close_f64!(dist("RBX", "=RCX"), 0.0);
+        assert_close_f64!(dist("RAX", "=RCX"), word_distance(0x1_003b, 0x15));
+    }
+
+    #[test]
+    fn test_bytes_distance() {
+        assert_close_f64!(bytes_distance(b"/bin/sh\0", b"/bin/sh\0"), 0.0);
+        assert_close_f64!(bytes_distance(b"/bin/sh\0\xff", b"/bin/sh\0"), 0.0);
+        assert_close_f64!(bytes_distance(b"/bin/sh\x01", b"/bin/sh\0"), 1.0);
+        assert_close_f64!(bytes_distance(b"/bin", b"/bin/sh\0"), 32.0);
+    }
 }
@@ -0,0 +1,280 @@
+//! The Age-Layered Population Structure, as described by Gregory Hornby in
+//! "ALPS: The Age-Layered Population Structure for Reducing the Problem of
+//! Premature Convergence" (GECCO 2006).
+//!
+//! The population is divided into layers, each with an age limit, and each
+//! arranged as a trivial geography. Creatures only compete with others of
+//! roughly their own age, and the bottom layer is periodically replaced by
+//! fresh, randomly generated creatures, so that new lineages have a chance
+//! to mature before they have to face the old ones.
+//!
+//! A creature's age is its `Genome::generation`: the number of generations
+//! since its oldest line of descent was randomly generated.
+use std::sync::Arc;
+
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::configure::Config;
+use crate::evolution::population::pier::Pier;
+use crate::evolution::population::trivial_geography::TrivialGeography;
+use crate::evolution::seed::initial_population;
+use crate::evolution::tournament::rank_by_front;
+use crate::evolution::{Genome, Phenome, World};
+use crate::observer::Observer;
+use crate::ontogenesis::Develop;
+use crate::util::random::{hash_seed_rng, Prng};
+
+pub struct Alps<E: Develop<P>, P: Phenome + 'static> {
+    pub layers: Vec<TrivialGeography<P>>,
+    pub config: Config,
+    pub iteration: usize,
+    pub observer: Observer<P>,
+    pub evaluator: E,
+    pub pier: Arc<Pier<P>>,
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Alps<E, P> {
+    pub fn new(config: &Config, observer: Observer<P>, evaluator: E, pier: Arc<Pier<P>>) -> Self
+    where
+        Self: Sized,
+    {
+        let config = config.clone();
+        assert!(
+            config.alps.num_layers > 0,
+            "ALPS needs at least one age layer"
+        );
+        assert!(
+            Self::layer_size(&config) > config.tournament.tournament_size,
+            "Each ALPS layer must be larger than a tournament"
+        );
+        log::debug!("Initializing {} age layers", config.alps.num_layers);
+        // any seeds start out in the bottom layer, with the other newcomers
+        let layers = (0..config.alps.num_layers)
+            .map(|i| {
+                if i == 0 {
+                    Self::seeded_layer(&config)
+                } else {
+                    Self::random_layer(&config, i)
+                }
+            })
+            .collect::<Vec<_>>();
+        log::debug!("population initialized");
+
+        Self {
+            layers,
+            config,
+            iteration: 0,
+            observer,
+            evaluator,
+            pier,
+        }
+    }
+
+    fn layer_size(config: &Config) -> usize {
+        config.pop_size / config.alps.num_layers
+    }
+
+    fn seeded_layer(config: &Config) -> TrivialGeography<P> {
+        let salt = hash_seed_rng(&0_usize).gen::<u64>();
+        let mut layer: TrivialGeography<P> =
+            initial_population(config, Self::layer_size(config), |i| {
+                P::random(config, salt ^ i as u64)
+            })
+            .into_iter()
+            .collect();
+        layer.set_radius(config.tournament.geographic_radius);
+        layer
+    }
+
+    fn random_layer<H: std::hash::Hash>(config: &Config, salt: H) -> TrivialGeography<P> {
+        let salt = hash_seed_rng(&salt).gen::<u64>();
+        let mut layer: TrivialGeography<P> = (0..Self::layer_size(config))
+            .into_par_iter()
+            .map(|i| P::random(config, salt ^ i as u64))
+            .collect();
+        layer.set_radius(config.tournament.geographic_radius);
+        layer
+    }
+
+    pub fn evolve(self) -> Self {
+        let Self {
+            mut layers,
+            observer,
+            mut evaluator,
+            config,
+            iteration,
+            pier,
+        } = self;
+
+        let mut rng = hash_seed_rng(&layers);
+        let generation_length = config.pop_size / config.tournament.num_offspring;
+
+        if iteration > 0 && iteration % (generation_length * config.alps.age_gap) == 0 {
+            Self::reseed(&mut layers, &config, iteration, &mut rng);
+        }
+
+        // Take the layers in turn, skipping any that have been drained by
+        // promotion below the size a tournament needs.
+        let num_layers = layers.len();
+        let needed = config.tournament.tournament_size;
+        let layer = match (0..num_layers)
+            .map(|i| (iteration + i) % num_layers)
+            .find(|&l| layers[l].len() > needed)
+        {
+            Some(layer) => layer,
+            None => {
+                log::warn!("Every age layer is too small for a tournament. Reseeding.");
+                Self::reseed(&mut layers, &config, iteration, &mut rng);
+                0
+            }
+        };
+        log::debug!(
+            "island {}, iteration {}: tournament in layer {}; layer sizes: {:?}",
+            config.island_identifier,
+            iteration,
+            layer,
+            layers.iter().map(TrivialGeography::len).collect::<Vec<_>>()
+        );
+
+        let combatants = layers[layer].choose_combatants(needed, &mut rng);
+
+        let (stale, current): (Vec<P>, Vec<P>) = combatants
+            .into_iter()
+            .partition(|p| p.is_fitness_stale(&config));
+        let combatants = evaluator
+            .evaluate(stale, &config)
+            .into_iter()
+            .chain(current.into_iter())
+            .map(|e| {
+                observer.observe(e.clone());
+                e
+            })
+            .collect::<Vec<P>>();
+
+        let mut combatants = rank_by_front(combatants);
+
+        // kill one off for every offspring to be produced
+        for _ in 0..config.tournament.num_offspring {
+            let _ = combatants.pop();
+        }
+
+        let mut survivors = combatants;
+
+        // A generation should be considered to have elapsed once
+        // `pop_size` offspring have been spawned.
+        if config.island_identifier == 0 && iteration % generation_length == 0 {
+            crate::increment_epoch_counter();
+            log::info!(
+                "New global epoch. Island #{} epoch is {}",
+                config.island_identifier,
+                iteration / generation_length
+            );
+        }
+
+        // NOTE: migration relies on tournaments being at least 1 larger than
+        // the number of parents plus the number of children
+        if survivors.len() > config.tournament.num_parents {
+            pier.exchange(&mut survivors, &config, &mut rng);
+        }
+
+        let parents = survivors
+            .iter_mut()
+            .take(config.tournament.num_parents)
+            .map(|p| {
+                p.incr_num_offspring(config.tournament.num_offspring);
+                &*p
+            })
+            .collect::<Vec<&P>>();
+
+        let offspring: Vec<P> = (0..config.tournament.num_offspring)
+            .map(|i| Genome::mate(&parents, &config, (iteration, i)))
+            .collect::<Vec<_>>();
+
+        // return everyone to the population, promoting those who have
+        // grown too old for this layer
+        for creature in survivors.into_iter().chain(offspring.into_iter()) {
+            if Self::too_old(&creature, layer, &config) {
+                Self::promote(&mut layers, layer + 1, creature, &config, &mut rng);
+            } else {
+                layers[layer].insert(creature).unwrap()
+            }
+        }
+
+        Self {
+            layers,
+            config,
+            iteration: iteration + 1,
+            observer,
+            evaluator,
+            pier,
+        }
+    }
+
+    fn too_old(creature: &P, layer: usize, config: &Config) -> bool {
+        config
+            .alps
+            .age_limit(layer)
+            .map_or(false, |limit| creature.generation() > limit)
+    }
+
+    /// Moves a creature into the lowest layer, at or above `layer`, whose
+    /// age limit it falls within. If that layer is full, the creature has
+    /// to displace a randomly chosen resident, and whichever of the two is
+    /// less fit dies.
+    fn promote(
+        layers: &mut [TrivialGeography<P>],
+        mut layer: usize,
+        creature: P,
+        config: &Config,
+        rng: &mut Prng,
+    ) {
+        if layer >= layers.len() {
+            return;
+        }
+        while Self::too_old(&creature, layer, config) {
+            layer += 1;
+        }
+        let target = &mut layers[layer];
+        if target.len() < Self::layer_size(config) {
+            target.insert(creature).unwrap();
+            return;
+        }
+        let resident = target
+            .extract(rng.gen_range(0, target.len()))
+            .expect("full layer had no residents");
+        let challenger_wins = match (creature.fitness(), resident.fitness()) {
+            (Some(c), Some(r)) => c.partial_cmp(r) != Some(std::cmp::Ordering::Greater),
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        let survivor = if challenger_wins { creature } else { resident };
+        target.insert(survivor).unwrap();
+    }
+
+    /// Replaces the bottom layer with randomly generated creatures. The
+    /// creatures it held are offered promotion to the layer above.
+    fn reseed(
+        layers: &mut [TrivialGeography<P>],
+        config: &Config,
+        iteration: usize,
+        rng: &mut Prng,
+    ) {
+        log::info!(
+            "Island #{} reseeding bottom age layer at iteration {}",
+            config.island_identifier,
+            iteration
+        );
+        let fresh = Self::random_layer(config, (iteration, config.random_seed));
+        let old = std::mem::replace(&mut layers[0], fresh);
+        for creature in old.into_iter() {
+            Self::promote(layers, 1, creature, config, rng);
+        }
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> World for Alps<E, P> {
+    fn evolve(self) -> Self {
+        Alps::evolve(self)
+    }
+}
@@ -1,230 +1,245 @@
-//! Implementation of the Lexicase selection algorithm, as described by Helmuth and Spector.
+//! Implementation of the Lexicase selection algorithm, as described by Helmuth and Spector,
+//! along with the epsilon-lexicase variant of La Cava, Spector and McPhee.
 //!
-//! Note that this is currently somewhat tailored to cases where we only need to develop (execute)
-//! the genotype _once_, thereby acquiring a phenotype that we can evaluate against a sequence of
-//! fitness cases. This is what we need, for instance, for ROPER's "register_pattern" task. It
-//! shouldn't be hard to generalize the algorithm beyond that.
+//! Note that this is tailored to cases where we only need to develop (execute) the genotype
+//! _once_, thereby acquiring a phenotype that we can evaluate against a sequence of fitness
+//! cases. This is what we need, for instance, for ROPER's "register_pattern" task, where
+//! each feature of the pattern, on each input, is a separate case.
 //!
-use std::fmt::Debug;
-use std::hash::Hash;
+use std::iter;
 use std::sync::Arc;
 
 use rand::Rng;
 
-use crate::configure::Config;
+use crate::configure::{Config, Epsilon};
 use crate::evolution::population::pier::Pier;
 use crate::evolution::population::shuffling_heap::ShufflingHeap;
-use crate::evolution::{Genome, Phenome};
+use crate::evolution::seed::initial_population;
+use crate::evolution::{Genome, Phenome, World};
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
-use crate::util::random::hash_seed_rng;
+use crate::util::random::{hash_seed_rng, Prng};
 
-pub struct Lexicase<Q: Hash + Debug, E: Develop<P>, P: Phenome + 'static> {
+pub struct Lexicase<E: Develop<P>, P: Phenome + 'static> {
     pub population: ShufflingHeap<P>,
-    pub problems: ShufflingHeap<Q>,
-    pub config: Arc<Config>,
-    pub best: Option<P>,
+    pub cases: Vec<P::Problem>,
+    pub config: Config,
     pub iteration: usize,
     pub observer: Observer<P>,
-    pub womb: E,
+    pub evaluator: E,
     pub pier: Arc<Pier<P>>,
 }
 
-impl<Q: Hash + Debug, E: Develop<P>, P: Phenome<Problem = Q> + Genome + 'static> Lexicase<Q, E, P> {
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Lexicase<E, P> {
     pub fn new(
         config: &Config,
         observer: Observer<P>,
-        womb: E,
+        evaluator: E,
         pier: Arc<Pier<P>>,
-        problems: Vec<Q>,
+        cases: Vec<P::Problem>,
     ) -> Self
     where
         Self: Sized,
     {
+        assert!(
+            !cases.is_empty(),
+            "Lexicase selection needs at least one fitness case"
+        );
+        let config = config.clone();
         log::debug!("Initializing population");
-        let config = Arc::new(config.clone());
-        let conf = config.clone();
-        let pop_size = config.pop_size;
-        let population: ShufflingHeap<P> = womb
-            .development_pipeline((0..pop_size).map(move |i| {
-                log::debug!("creating phenome {}/{}", i, pop_size);
-                P::random(&conf, i)
-            }))
-            .into_iter()
-            .map(|phenome| {
-                observer.observe(phenome.clone());
-                phenome
-            })
-            .collect();
+        let population: ShufflingHeap<P> = initial_population(&config, config.pop_size, |i| {
+            log::debug!("creating phenome {}/{}", i, config.pop_size);
+            P::random(&config, i)
+        })
+        .into_iter()
+        .collect();
         log::debug!("population initialized");
 
-        let problems: ShufflingHeap<Q> = problems.into_iter().collect();
-
         Self {
             population,
-            problems,
+            cases,
             config,
-            best: None,
             iteration: 0,
             observer,
-            womb,
+            evaluator,
             pier,
         }
     }
 
     pub fn evolve(self) -> Self {
         let Self {
-            mut population,
-            mut problems,
+            population,
+            cases,
             config,
-            best,
             iteration,
-            mut observer,
-            womb,
+            observer,
+            mut evaluator,
             pier,
         } = self;
 
         let mut rng = hash_seed_rng(&(iteration as u64 ^ config.random_seed));
 
-        // The idea here is to go through the problems in random order, and incrementally
-        // filter out any members of the population that fail to optimally solve each problem.
-
-        let mut next_population = ShufflingHeap::new(&rng.gen::<u64>());
-        let mut next_problems = ShufflingHeap::new(&rng.gen::<u64>());
-        //log::debug!("problems: {:?}", problems);
-        let num_problems = problems.len();
-        let mut problems_solved = 0;
-        while let Some(problem) = problems.pop() {
-            // In most cases, the developed phenotypes won't actually need re-development,
-            // and so `development_pipeline` will act like a simple pass-through.
-            let (mut fail, mut pass): (ShufflingHeap<P>, ShufflingHeap<P>) = womb
-                .development_pipeline(population.into_iter())
-                .into_iter()
-                // Here, we split the population into those that pass the test, and those
-                // that fail it.
-                .partition(|creature| creature.fails(&problem));
-
-            next_problems.push(problem);
-            if pass.len() > 0 {
-                problems_solved += 1;
-            }
-
-            // log::debug!("# fail: {}, # pass: {}", fail.len(), pass.len());
-            if problems.len() == 0 && pass.len() > 0 {
-                // if we just popped the last problem
-                // and we have some creatures who have passed,
-                // then the evolutionary process is complete!
-                log::info!("Solution(s) found!");
-                while let Some(champion) = pass.pop() {
-                    log::info!("{:?}", champion);
-
-                    // TODO: Log this! have the observer handle it.
-                }
-                observer.stop_evolution();
-                population = pass;
-                break;
-            }
+        // Only the offspring and immigrants, and, in dynamic mode, those
+        // with stale scores, need to be developed and scored.
+        let (stale, current): (Vec<P>, Vec<P>) = population
+            .into_iter()
+            .partition(|p| p.is_fitness_stale(&config));
+        let mut population = evaluator
+            .evaluate(stale, &config)
+            .into_iter()
+            .map(|p| {
+                observer.observe(p.clone());
+                p
+            })
+            .chain(current.into_iter())
+            .collect::<Vec<P>>();
 
-            while pass.len() < config.tournament.num_parents {
-                pass.push(fail.pop().unwrap());
-            }
-            population = pass;
-            next_population.extend(fail.into_iter());
-            if population.len() == config.tournament.num_parents {
-                break;
-            }
-        }
-        // If any problems are remaining, dump them into the next_problems heap,
-        // shuffling them in the process.
+        let errors = population
+            .iter()
+            .map(|p| cases.iter().map(|c| p.case_error(c)).collect::<Vec<f64>>())
+            .collect::<Vec<Vec<f64>>>();
         log::debug!(
-            "iteration {}: solved {} of {} problems",
+            "island {}, iteration {}: {} of {} cases solved",
+            config.island_identifier,
             iteration,
-            problems_solved,
-            num_problems,
+            (0..cases.len())
+                .filter(|&c| errors.iter().any(|e| e[c] == 0.0))
+                .count(),
+            cases.len(),
         );
-        problems.into_iter().for_each(|p| next_problems.push(p));
-        // TODO: I don't really know the best way to pass everything off
-        // to the observer. Cloning the entire population each generation
-        // feels excessive. Is excessive. I could just clone and observe the
-        // offspring, but then they're unevaluated. But what if I evaluate
-        // each offspring as it's sprung?
-
-        // let next_population = next_population
-        //     .into_iter()
-        //     .map(|p| observer.observe(p.clone()))
-        //     .collect::<ShufflingHeap<P>>();
-
-        if crate::keep_going() {
-            debug_assert_eq!(
-                population.len(),
-                config.tournament.num_parents,
-                "not enough left in the population to breed"
-            );
 
-            let mut parents = Vec::new();
-            for _ in 0..config.tournament.num_parents {
-                let p = population.pop().unwrap();
-                // Here's what we'll do: we'll restrict our observation to the individuals
-                // that are eventually selected as parents. This feels like an okay compromise.
-                // But it needs to be noted in any experimental reports. Can we filter our
-                // tournament, etc., observations in a similar fashion?
-                // NOTE: we'll observe the dead, too. No need to clone them.
-                // Maybe the observer should hold onto some validation/testing data!
-                // That would be the best way to do things. We could plot progress in
-                // terms of *that* data.
-                //observer.observe(p.clone());
-                parents.push(p)
-            }
-            for _ in 0..config.tournament.num_offspring {
-                let parents = parents.iter().collect::<Vec<&P>>();
-                let offspring = Genome::mate(&parents, &config);
-                // I could evaluate and observe the offspring here, as they're generated.
-                // Then add a pass for the initial seed population.
-                // But here, the observer doesn't see the case evaluations!
-
-                // NOTE: Non-elitist replacement
-                // Should we observe the dead?
-                //observer.observe(next_population.pop());
-                let _dead = next_population.pop();
-                let offspring = womb.develop(offspring);
-                observer.observe(offspring.clone());
-                next_population.push(offspring);
-            }
-            // Return the parents to the population
-            next_population.extend(parents.into_iter());
-            // A generation should be considered to have elapsed once
-            // `pop_size` offspring have been spawned.
-            if iteration % (config.pop_size / config.tournament.num_offspring) == 0 {
-                crate::increment_epoch_counter();
-                // if rng.gen_range(0.0, 1.0) < config.tournament.migration_rate {
-                //     log::info!("Attempting migration...");
-                //     if let Some(immigrant) = pier.disembark() {
-                //         log::info!("Found immigrant on pier");
-                //         let emigrant = next_population.pop().unwrap();
-                //         if let Err(_emigrant) = pier.embark(emigrant) {
-                //             log::error!("emigration failure, do something!");
-                //         }
-                //         next_population.push(immigrant);
-                //     }
-                // }
-            }
+        let epsilon = &config.lexicase.epsilon;
+        let parent_indices = iter::repeat(())
+            .take(config.tournament.num_parents)
+            .map(|()| Self::select(&errors, epsilon, &mut rng))
+            .collect::<Vec<usize>>();
+        for i in parent_indices.iter() {
+            population[*i].incr_num_offspring(config.tournament.num_offspring);
+        }
+        let offspring: Vec<P> = {
+            let parents = parent_indices
+                .iter()
+                .map(|i| &population[*i])
+                .collect::<Vec<&P>>();
+            (0..config.tournament.num_offspring)
+                .map(|i| Genome::mate(&parents, &config, (iteration, i)))
+                .collect()
+        };
+
+        // The emigrant is chosen by lexicase, too, so that the other islands
+        // receive someone who excels at something.
+        let mut emigrant = None;
+        let mut immigrant = None;
+        if rng.gen_range(0.0, 1.0) < config.tournament.migration_rate {
+            log::debug!("Attempting migration...");
+            let i = Self::select(&errors, epsilon, &mut rng);
+            emigrant = Some(population.swap_remove(i));
+        } else {
+            immigrant = pier.disembark(config.island_identifier, &mut rng);
+        }
 
-            debug_assert_eq!(
-                next_population.len(),
-                config.pop_size,
-                "the population is leaking!"
+        let mut next_population = ShufflingHeap::new(&rng.gen::<u64>());
+        next_population.extend(population.into_iter());
+        if let Some(emigrant) = emigrant {
+            if let Err(emigrant) = pier.embark(config.island_identifier, emigrant, &mut rng) {
+                log::debug!("Pier full, returning emigrant to population");
+                next_population.push(emigrant);
+            }
+        }
+        if let Some(immigrant) = immigrant {
+            log::debug!(
+                "{} has arrived from the pier of island {}",
+                immigrant.name(),
+                config.island_identifier
             );
+            next_population.push(immigrant);
+        }
+
+        // NOTE: Non-elitist replacement. Since the heap pops at random,
+        // this kills off randomly chosen members of the population.
+        while next_population.len() + offspring.len() > config.pop_size {
+            let _dead = next_population.pop();
+        }
+        next_population.extend(offspring.into_iter());
+
+        // A generation should be considered to have elapsed once
+        // `pop_size` offspring have been spawned.
+        if config.island_identifier == 0
+            && iteration % (config.pop_size / config.tournament.num_offspring) == 0
+        {
+            crate::increment_epoch_counter();
         }
 
         Self {
             population: next_population,
-            problems: next_problems,
+            cases,
             config,
-            best,
             iteration: iteration + 1,
             observer,
-            womb,
+            evaluator,
             pier,
         }
     }
+
+    /// Runs a single lexicase selection event over the error matrix, whose
+    /// rows are creatures and whose columns are fitness cases, and returns
+    /// the index of the creature selected. The cases are taken in a fresh
+    /// random order each time, and the candidates are winnowed down to
+    /// those within epsilon of the best error on each case, until either one
+    /// candidate or no cases remain.
+    fn select(errors: &[Vec<f64>], epsilon: &Epsilon, rng: &mut Prng) -> usize {
+        let num_cases = errors.first().map_or(0, Vec::len);
+        let mut order = ShufflingHeap::new(&rng.gen::<u64>());
+        order.extend(0..num_cases);
+        let mut candidates = (0..errors.len()).collect::<Vec<usize>>();
+
+        while candidates.len() > 1 {
+            let case = match order.pop() {
+                Some(case) => case,
+                None => break,
+            };
+            let case_errors = candidates
+                .iter()
+                .map(|i| errors[*i][case])
+                .collect::<Vec<f64>>();
+            let best = case_errors.iter().cloned().fold(std::f64::MAX, f64::min);
+            let threshold = best
+                + match epsilon {
+                    Epsilon::Exact => 0.0,
+                    Epsilon::Fixed(e) => *e,
+                    Epsilon::MedianAbsoluteDeviation => median_absolute_deviation(&case_errors),
+                };
+            candidates = candidates
+                .into_iter()
+                .zip(case_errors.into_iter())
+                .filter(|(_, e)| *e <= threshold)
+                .map(|(i, _)| i)
+                .collect();
+        }
+
+        candidates[rng.gen_range(0, candidates.len())]
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> World for Lexicase<E, P> {
+    fn evolve(self) -> Self {
+        Lexicase::evolve(self)
+    }
+}
+
+fn median_absolute_deviation(xs: &[f64]) -> f64 {
+    let median = stats::median(xs.iter().cloned()).unwrap_or(0.0);
+    stats::median(xs.iter().map(|x| (x - median).abs())).unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_median_absolute_deviation() {
+        let xs = [1.0, 1.0, 2.0, 2.0, 4.0, 6.0, 9.0];
+        assert!((median_absolute_deviation(&xs) - 1.0).abs() < std::f64::EPSILON);
+        assert!(median_absolute_deviation(&[0.5]).abs() < std::f64::EPSILON);
+    }
 }
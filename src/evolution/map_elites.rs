@@ -0,0 +1,286 @@
+//! MAP-Elites (Mouret & Clune, 2015). Rather than a population, there is an
+//! archive, divided into cells along a handful of behavioural features, and
+//! each cell keeps only the fittest creature that has landed in it. Parents
+//! are drawn uniformly from the occupied cells, so that the search is spread
+//! across the whole range of behaviours rather than drawn towards the
+//! single fittest one.
+//!
+//! The world doesn't know how to measure the features itself. It's handed a
+//! describer, which returns a creature's feature values in the order of
+//! `map_elites.dimensions`, or `None` if the creature can't be placed.
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use rand::seq::SliceRandom;
+use serde::Serialize;
+
+use crate::configure::{Config, FeatureDimension};
+use crate::evolution::seed::initial_population;
+use crate::evolution::{Genome, Phenome, World};
+use crate::increment_epoch_counter;
+use crate::observer::Observer;
+use crate::ontogenesis::Develop;
+use crate::util::dump::dump;
+use crate::util::random::hash_seed_rng;
+
+pub type Describer<P> = Box<dyn Fn(&P) -> Option<Vec<f64>> + Send>;
+
+pub struct MapElites<E: Develop<P>, P: Phenome + Genome + 'static> {
+    /// The elite of each occupied cell, keyed by its bin along each dimension.
+    pub archive: BTreeMap<Vec<usize>, P>,
+    /// The creatures awaiting evaluation and placement in the archive.
+    pub pending: Vec<P>,
+    pub config: Arc<Config>,
+    pub observer: Observer<P>,
+    pub evaluator: E,
+    pub iteration: usize,
+    describer: Describer<P>,
+}
+
+#[derive(Serialize)]
+struct Cell<'a, P> {
+    cell: &'a [usize],
+    fitness: Option<f64>,
+    creature: &'a P,
+}
+
+/// Lower scalar fitness is better.
+fn energy<P: Phenome>(creature: &P, config: &Config) -> f64 {
+    creature
+        .scalar_fitness(config.fitness.weighting())
+        .unwrap_or(std::f64::MAX)
+}
+
+/// The cell into which the feature values fall.
+fn cell(values: &[f64], dimensions: &[FeatureDimension]) -> Vec<usize> {
+    values
+        .iter()
+        .zip(dimensions.iter())
+        .map(|(v, dim)| {
+            let bin = (v / dim.bin_width).floor().max(0.0) as usize;
+            bin.min(dim.bins.saturating_sub(1))
+        })
+        .collect()
+}
+
+/// Puts the creature in the cell if it's vacant, or if the creature beats
+/// the incumbent, and says whether it did.
+fn place<P: Phenome>(
+    archive: &mut BTreeMap<Vec<usize>, P>,
+    cell: Vec<usize>,
+    creature: P,
+    config: &Config,
+) -> bool {
+    let vacant_or_beaten = archive.get(&cell).map_or(true, |elite| {
+        energy(&creature, config) < energy(elite, config)
+    });
+    if vacant_or_beaten {
+        archive.insert(cell, creature);
+    }
+    vacant_or_beaten
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> MapElites<E, P> {
+    pub fn new(
+        config: &Config,
+        observer: Observer<P>,
+        evaluator: E,
+        describer: Describer<P>,
+    ) -> Self {
+        assert!(
+            !config.map_elites.dimensions.is_empty(),
+            "MAP-Elites needs at least one feature dimension"
+        );
+        let pending = initial_population(config, config.pop_size, |i| P::random(config, i));
+
+        Self {
+            archive: BTreeMap::new(),
+            pending,
+            config: Arc::new(config.clone()),
+            observer,
+            evaluator,
+            iteration: 0,
+            describer,
+        }
+    }
+
+    pub fn evolve(self) -> Self {
+        let Self {
+            mut archive,
+            pending,
+            config,
+            observer,
+            mut evaluator,
+            iteration,
+            describer,
+        } = self;
+
+        let dimensions = &config.map_elites.dimensions;
+        let describe = |creature: &P| describer(creature).map(|values| cell(&values, dimensions));
+        let newcomers = evaluator
+            .evaluate(pending, &config)
+            .into_iter()
+            .map(|creature| (describe(&creature), creature))
+            .collect::<Vec<(Option<Vec<usize>>, P)>>();
+
+        // An incumbent scored under an earlier stage, or too many epochs
+        // ago, is re-evaluated before it has to defend its cell.
+        let stale_cells = newcomers
+            .iter()
+            .filter_map(|(cell, _)| cell.as_ref())
+            .filter(|cell| {
+                archive
+                    .get(*cell)
+                    .map_or(false, |elite| elite.is_fitness_stale(&config))
+            })
+            .cloned()
+            .collect::<BTreeSet<Vec<usize>>>();
+        let incumbents = stale_cells
+            .iter()
+            .filter_map(|cell| archive.remove(cell))
+            .collect::<Vec<P>>();
+        for elite in evaluator.evaluate(incumbents, &config) {
+            if let Some(cell) = describe(&elite) {
+                place(&mut archive, cell, elite, &config);
+            }
+        }
+
+        let mut placed = 0;
+        for (cell, creature) in newcomers {
+            if let Some(cell) = cell {
+                if place(&mut archive, cell.clone(), creature.clone(), &config) {
+                    log::debug!("[{}] {} takes cell {:?}", iteration, creature.name(), cell);
+                    placed += 1;
+                }
+            }
+            observer.observe(creature);
+        }
+        log::info!(
+            "[{}] {} new elites, {} cells occupied",
+            iteration,
+            placed,
+            archive.len()
+        );
+
+        let mut rng = hash_seed_rng(&(config.random_seed, iteration));
+        let elites = archive.values().collect::<Vec<&P>>();
+        let pending = if elites.is_empty() {
+            // nothing could be placed, so start again from scratch
+            (0..config.pop_size)
+                .map(|i| P::random(&config, (iteration, i)))
+                .collect()
+        } else {
+            (0..config.map_elites.batch_size)
+                .map(|i| {
+                    let parents = (0..config.tournament.num_parents)
+                        .filter_map(|_| elites.choose(&mut rng).copied())
+                        .collect::<Vec<&P>>();
+                    Genome::mate(&parents, &config, (iteration, i))
+                })
+                .collect()
+        };
+
+        let world = Self {
+            archive,
+            pending,
+            config,
+            observer,
+            evaluator,
+            iteration: iteration + 1,
+            describer,
+        };
+        let dump_every = world.config.map_elites.dump_every;
+        if dump_every > 0 && world.iteration % dump_every == 0 {
+            world.dump();
+        }
+        increment_epoch_counter();
+        world
+    }
+
+    /// Dumps the archive as gzipped JSON, and its fitness landscape as a
+    /// CSV, with a row for each occupied cell, suitable for a heatmap.
+    pub fn dump(&self) {
+        let dir = format!("{}/map_elites", self.config.data_directory());
+        let path = format!("{}/archive_{}.json.gz", dir, self.iteration);
+        log::info!(
+            "Dumping MAP-Elites archive of {} cells to {}",
+            self.archive.len(),
+            path
+        );
+        let cells = self
+            .archive
+            .iter()
+            .map(|(cell, creature)| Cell {
+                cell,
+                fitness: creature.scalar_fitness(self.config.fitness.weighting()),
+                creature,
+            })
+            .collect::<Vec<_>>();
+        if let Err(e) = dump(&cells, &path) {
+            log::error!("Failed to dump MAP-Elites archive: {:?}", e);
+        }
+
+        let path = format!("{}/heatmap_{}.csv", dir, self.iteration);
+        if let Err(e) = self.write_heatmap(&path) {
+            log::error!("Failed to write MAP-Elites heatmap to {}: {:?}", path, e);
+        }
+    }
+
+    fn write_heatmap(&self, path: &str) -> Result<(), csv::Error> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_path(path)?;
+        let mut header = self
+            .config
+            .map_elites
+            .dimensions
+            .iter()
+            .map(|dim| format!("{:?}", dim.feature))
+            .collect::<Vec<String>>();
+        header.push("fitness".to_string());
+        header.push("name".to_string());
+        writer.write_record(&header)?;
+        for (cell, creature) in self.archive.iter() {
+            let mut record = cell.iter().map(usize::to_string).collect::<Vec<String>>();
+            record.push(format!("{}", energy(creature, &self.config)));
+            record.push(creature.name().to_string());
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> World for MapElites<E, P> {
+    fn evolve(self) -> Self {
+        MapElites::evolve(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::Feature;
+
+    use super::*;
+
+    #[test]
+    fn test_cell() {
+        let dimensions = vec![
+            FeatureDimension {
+                feature: Feature::GadgetsExecuted,
+                bins: 4,
+                bin_width: 1.0,
+            },
+            FeatureDimension {
+                feature: Feature::ChainLength,
+                bins: 8,
+                bin_width: 4.0,
+            },
+        ];
+        assert_eq!(cell(&[0.0, 0.0], &dimensions), vec![0, 0]);
+        assert_eq!(cell(&[2.0, 7.9], &dimensions), vec![2, 1]);
+        assert_eq!(cell(&[10.0, 100.0], &dimensions), vec![3, 7]);
+        assert_eq!(cell(&[-1.0, 4.0], &dimensions), vec![0, 1]);
+    }
+}
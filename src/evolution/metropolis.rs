@@ -1,109 +1,405 @@
+//! Local search by the Metropolis algorithm. A specimen is varied, and the
+//! variation replaces it if it's fitter, or otherwise with a probability
+//! that shrinks as the difference in fitness grows and as the temperature
+//! falls. The temperature can be lowered over time, by one of the cooling
+//! schedules in `configure::Cooling`, which makes this simulated annealing.
+//!
+//! With more than one replica, this becomes parallel tempering: each
+//! replica runs at its own temperature, on its own thread, and every
+//! `swap_every` iterations neighbouring replicas may trade specimens, so
+//! that good specimens found by the hot, exploratory replicas sink down
+//! to the cold ones to be refined.
 use std::sync::atomic::Ordering;
 
 use rand::Rng;
+use rayon::prelude::*;
 
-use crate::configure::Config;
-use crate::evolution::{Genome, Phenome};
+use crate::configure::{Config, Cooling};
+use crate::evolution::seed::initial_population;
+use crate::evolution::{Genome, Phenome, World};
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
 use crate::util::random::hash_seed_rng;
 use crate::EPOCH_COUNTER;
 
-pub struct Metropolis<E: Develop<P>, P: Phenome + Genome + 'static> {
+pub struct Replica<E: Develop<P>, P: Phenome + Genome + 'static> {
     pub specimen: P,
+    pub temperature: f64,
+    pub evaluator: E,
+    /// The number of variations accepted, and proposed, since the
+    /// temperature was last adapted.
+    accepted: usize,
+    proposed: usize,
+}
+
+pub struct Metropolis<E: Develop<P>, P: Phenome + Genome + 'static> {
+    /// The replicas, from coldest to hottest.
+    pub replicas: Vec<Replica<E, P>>,
     pub config: Config,
     pub iteration: usize,
     pub observer: Observer<P>,
-    pub evaluator: E,
     pub best: Option<P>,
 }
 
-impl<E: Develop<P>, P: Phenome + Genome + 'static> Metropolis<E, P> {
-    pub fn new(config: &Config, observer: Observer<P>, evaluator: E) -> Self {
-        let specimen = P::random(&config, 1);
+fn evaluate_one<E: Develop<P>, P: Phenome + 'static>(
+    evaluator: &mut E,
+    creature: P,
+    config: &Config,
+) -> P {
+    evaluator
+        .evaluate(vec![creature], config)
+        .pop()
+        .expect("Evaluator lost a creature")
+}
+
+/// Lower scalar fitness is better, so it serves as the energy.
+fn energy<P: Phenome>(creature: &P, config: &Config) -> f64 {
+    creature
+        .scalar_fitness(config.fitness.weighting())
+        .unwrap_or(std::f64::MAX)
+}
+
+impl<E: Develop<P> + Send, P: Phenome + Genome + 'static> Replica<E, P> {
+    /// Proposes a variation of the specimen, and decides whether to
+    /// accept it, then cools.
+    fn step(&mut self, iteration: usize, config: &Config) {
+        // a specimen scored under an earlier stage, or too many epochs ago,
+        // can't be compared with its variation until it's scored again
+        if self.specimen.is_fitness_stale(config) {
+            self.specimen = evaluate_one(&mut self.evaluator, self.specimen.clone(), config);
+        }
+        // replicas may hold the same specimen, so the temperature is
+        // mixed into the seed to keep their variations apart
+        let mut rng = hash_seed_rng(&(&self.specimen, iteration, self.temperature.to_bits()));
+        let mut variation = P::crossover(&[&self.specimen, &self.specimen], config, &mut rng);
+        variation.mutate(config, &mut rng);
+        let variation = evaluate_one(&mut self.evaluator, variation, config);
+
+        let delta = energy(&variation, config) - energy(&self.specimen, config);
+        self.proposed += 1;
+        // A fitter variation always replaces the specimen. A less fit one
+        // has a chance of doing so that falls off with the difference.
+        if delta <= 0.0 || rng.gen_range(0.0, 1.0) < (-delta / self.temperature).exp() {
+            log::debug!(
+                "[{}] T = {:.4}: accepting {} (delta {})",
+                iteration,
+                self.temperature,
+                variation.name(),
+                delta
+            );
+            self.specimen = variation;
+            self.accepted += 1;
+        }
+        self.cool(
+            &config.metropolis.cooling,
+            config.metropolis.min_temperature,
+        );
+    }
+
+    fn cool(&mut self, cooling: &Cooling, min_temperature: f64) {
+        let temperature = match *cooling {
+            Cooling::Constant => self.temperature,
+            Cooling::Linear(step) => self.temperature - step,
+            Cooling::Exponential(rate) => self.temperature * rate,
+            Cooling::Adaptive {
+                target_acceptance,
+                window,
+                factor,
+            } => {
+                if self.proposed < window {
+                    return;
+                }
+                let acceptance = self.accepted as f64 / self.proposed as f64;
+                self.accepted = 0;
+                self.proposed = 0;
+                if acceptance > target_acceptance {
+                    self.temperature / factor
+                } else {
+                    self.temperature * factor
+                }
+            }
+        };
+        self.temperature = temperature.max(min_temperature);
+    }
+}
+
+impl<E: Develop<P> + Send, P: Phenome + Genome + 'static> Metropolis<E, P> {
+    /// Takes one evaluator for each of the `metropolis.replicas` replicas,
+    /// since they evaluate their specimens in parallel.
+    pub fn new(config: &Config, observer: Observer<P>, evaluators: Vec<E>) -> Self {
+        assert_eq!(
+            evaluators.len(),
+            config.metropolis.replicas.max(1),
+            "Each Metropolis replica needs its own evaluator"
+        );
+        let specimens = initial_population(config, evaluators.len(), |i| {
+            P::random(config, (config.random_seed, i))
+        });
+        let replicas = evaluators
+            .into_iter()
+            .zip(specimens.into_iter())
+            .enumerate()
+            .map(|(i, (evaluator, specimen))| Replica {
+                specimen,
+                temperature: config.metropolis.temperature
+                    * config.metropolis.temperature_ratio.powi(i as i32),
+                evaluator,
+                accepted: 0,
+                proposed: 0,
+            })
+            .collect::<Vec<_>>();
+        log::info!(
+            "Metropolis replica temperatures: {:?}",
+            replicas.iter().map(|r| r.temperature).collect::<Vec<_>>()
+        );
 
         Self {
-            specimen,
+            replicas,
             config: config.clone(),
             iteration: 0,
             observer,
-            evaluator,
             best: None,
         }
     }
 
     pub fn evolve(self) -> Self {
         let Self {
-            specimen,
+            mut replicas,
             config,
             iteration,
             observer,
-            evaluator,
             best,
         } = self;
 
         EPOCH_COUNTER.fetch_add(1, Ordering::Relaxed);
 
-        let mut specimen = if specimen.fitness().is_none() {
-            evaluator.develop(specimen)
-        } else {
-            specimen
-        };
-        let variation = Genome::mate(&[&specimen, &specimen], &config);
-        let variation = evaluator.develop(variation);
-
-        let mut rng = hash_seed_rng(&specimen);
-        let vari_fit = variation.scalar_fitness(&config.fitness.weighting).unwrap();
-        let spec_fit = specimen.scalar_fitness(&config.fitness.weighting).unwrap();
-        let delta = if (vari_fit - spec_fit).abs() < std::f64::EPSILON {
-            0.0
-        } else {
-            1.0 / (vari_fit - spec_fit)
-        };
+        replicas
+            .par_iter_mut()
+            .for_each(|replica| replica.step(iteration, &config));
 
-        // if the variation is fitter, replace.
-        // otherwise, let there be a chance of replacement inversely proportionate to the
-        // difference in fitness.
-        if delta < 0.0 || ((-delta).exp()) < rng.gen_range(0.0, 1.0) {
-            //if delta < 0.0 { // pure hillclimbing
-            specimen = variation;
-            log::info!(
-                "[{}] best: {:?}. specimen: {}, variation: {} (delta {}), switching",
-                iteration,
-                best.as_ref()
-                    .and_then(|p| p.scalar_fitness(&config.fitness.weighting)),
-                spec_fit,
-                vari_fit,
-                delta
-            );
-        };
-        observer.observe(specimen.clone());
+        if replicas.len() > 1 && iteration % config.metropolis.swap_every.max(1) == 0 {
+            Self::swap(&mut replicas, iteration, &config);
+        }
 
-        let mut updated_best = false;
-        let best = match best {
-            Some(b)
-                if specimen.scalar_fitness(&config.fitness.weighting).unwrap()
-                    < b.scalar_fitness(&config.fitness.weighting).unwrap() =>
-            {
-                updated_best = true;
-                Some(specimen.clone())
+        for replica in replicas.iter() {
+            observer.observe(replica.specimen.clone());
+        }
+
+        // the best so far is scored again if its energy has gone stale
+        let best = best.map(|b| {
+            if b.is_fitness_stale(&config) {
+                evaluate_one(&mut replicas[0].evaluator, b, &config)
+            } else {
+                b
             }
-            None => {
-                updated_best = true;
-                Some(specimen.clone())
+        });
+
+        // the coldest replica's specimen is usually the fittest, but not always
+        let fittest = replicas
+            .iter()
+            .map(|r| &r.specimen)
+            .min_by(|a, b| {
+                energy(*a, &config)
+                    .partial_cmp(&energy(*b, &config))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .expect("No Metropolis replicas");
+        let best = match best {
+            Some(b) if energy(&b, &config) <= energy(fittest, &config) => Some(b),
+            _ => {
+                log::info!("[{}] new best: {:?}", iteration, fittest);
+                Some(fittest.clone())
             }
-            _ => best,
         };
-        if updated_best {
-            log::info!("new best: {:?}", best.as_ref().unwrap());
-        }
+
         Self {
-            specimen,
+            replicas,
             config,
             iteration: iteration + 1,
             observer,
-            evaluator,
             best,
         }
     }
+
+    /// Offers each pair of neighbouring replicas the chance to trade
+    /// specimens, with the usual replica exchange criterion. Pairs are
+    /// taken from the even or odd replicas in alternate rounds, so that
+    /// every boundary in the temperature ladder gets its turn.
+    fn swap(replicas: &mut [Replica<E, P>], iteration: usize, config: &Config) {
+        let round = iteration / config.metropolis.swap_every.max(1);
+        let mut rng = hash_seed_rng(&(config.random_seed, iteration));
+        for i in (round % 2..replicas.len() - 1).step_by(2) {
+            let (cold, hot) = (&replicas[i], &replicas[i + 1]);
+            let exponent = (energy(&cold.specimen, config) - energy(&hot.specimen, config))
+                * (1.0 / cold.temperature - 1.0 / hot.temperature);
+            if exponent >= 0.0 || rng.gen_range(0.0, 1.0) < exponent.exp() {
+                log::debug!(
+                    "[{}] swapping specimens between T = {:.4} and T = {:.4}",
+                    iteration,
+                    cold.temperature,
+                    hot.temperature
+                );
+                let (lower, upper) = replicas.split_at_mut(i + 1);
+                std::mem::swap(&mut lower[i].specimen, &mut upper[0].specimen);
+            }
+        }
+    }
+}
+
+impl<E: Develop<P> + Send, P: Phenome + Genome + 'static> World for Metropolis<E, P> {
+    fn evolve(self) -> Self {
+        Metropolis::evolve(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::examples::linear_gp::Creature;
+    use crate::fitness::Weighted;
+
+    use super::*;
+
+    /// The replicas under test are never asked to evaluate anything.
+    struct Idle;
+
+    impl Develop<Creature> for Idle {
+        fn develop(&self, _ob: Creature) -> Creature {
+            unreachable!("nothing should be developed")
+        }
+
+        fn apply_fitness_function(&mut self, _ob: Creature) -> Creature {
+            unreachable!("nothing should be evaluated")
+        }
+
+        fn development_pipeline<I: 'static + Iterator<Item = Creature> + Send>(
+            &self,
+            _inbound: I,
+        ) -> Vec<Creature> {
+            unreachable!("nothing should be developed")
+        }
+    }
+
+    fn energy_config() -> Config {
+        let mut config = Config::default();
+        config.fitness.weighting = "x".to_string();
+        config
+    }
+
+    fn replica(energy: f64, temperature: f64) -> Replica<Idle, Creature> {
+        let mut fitness = Weighted::new("x");
+        fitness.insert("x", energy);
+        let mut specimen = Creature::default();
+        specimen.set_fitness(fitness);
+        Replica {
+            specimen,
+            temperature,
+            evaluator: Idle,
+            accepted: 0,
+            proposed: 0,
+        }
+    }
+
+    fn assert_temperature(replica: &Replica<Idle, Creature>, expected: f64) {
+        assert!(
+            (replica.temperature - expected).abs() < 1e-9,
+            "temperature {} != {}",
+            replica.temperature,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_constant_cooling() {
+        let mut replica = replica(0.0, 1.0);
+        for _ in 0..10 {
+            replica.cool(&Cooling::Constant, 0.1);
+        }
+        assert_temperature(&replica, 1.0);
+    }
+
+    #[test]
+    fn test_linear_cooling() {
+        let mut replica = replica(0.0, 1.0);
+        for expected in &[0.7, 0.4, 0.1, 0.05, 0.05] {
+            replica.cool(&Cooling::Linear(0.3), 0.05);
+            assert_temperature(&replica, *expected);
+        }
+    }
+
+    #[test]
+    fn test_exponential_cooling() {
+        let mut replica = replica(0.0, 1.0);
+        for expected in &[0.5, 0.25, 0.2, 0.2] {
+            replica.cool(&Cooling::Exponential(0.5), 0.2);
+            assert_temperature(&replica, *expected);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_cooling() {
+        let cooling = Cooling::Adaptive {
+            target_acceptance: 0.5,
+            window: 4,
+            factor: 2.0,
+        };
+        let mut replica = replica(0.0, 1.0);
+
+        // nothing changes until a full window has been proposed
+        replica.accepted = 3;
+        replica.proposed = 3;
+        replica.cool(&cooling, 0.01);
+        assert_temperature(&replica, 1.0);
+        assert_eq!((replica.accepted, replica.proposed), (3, 3));
+
+        // accepting too much cools the replica, and starts a new window
+        replica.proposed = 4;
+        replica.cool(&cooling, 0.01);
+        assert_temperature(&replica, 0.5);
+        assert_eq!((replica.accepted, replica.proposed), (0, 0));
+
+        // accepting too little warms it up again
+        replica.accepted = 1;
+        replica.proposed = 4;
+        replica.cool(&cooling, 0.01);
+        assert_temperature(&replica, 1.0);
+        assert_eq!((replica.accepted, replica.proposed), (0, 0));
+
+        // but never below the floor
+        replica.accepted = 4;
+        replica.proposed = 4;
+        replica.cool(&cooling, 0.8);
+        assert_temperature(&replica, 0.8);
+    }
+
+    fn energies(replicas: &[Replica<Idle, Creature>], config: &Config) -> Vec<f64> {
+        replicas
+            .iter()
+            .map(|r| energy(&r.specimen, config))
+            .collect()
+    }
+
+    #[test]
+    fn test_swap() {
+        let mut config = energy_config();
+        config.metropolis.swap_every = 1;
+
+        // a fitter specimen in the hotter replica always sinks down
+        let mut replicas = vec![replica(3.0, 1.0), replica(2.0, 2.0), replica(1.0, 4.0)];
+        // even rounds offer the boundary between the first two replicas
+        Metropolis::<Idle, Creature>::swap(&mut replicas, 0, &config);
+        assert_eq!(energies(&replicas, &config), vec![2.0, 3.0, 1.0]);
+        // odd rounds, the boundary between the next two
+        Metropolis::<Idle, Creature>::swap(&mut replicas, 1, &config);
+        assert_eq!(energies(&replicas, &config), vec![2.0, 1.0, 3.0]);
+        // the temperatures stay where they were
+        let temperatures = replicas.iter().map(|r| r.temperature).collect::<Vec<_>>();
+        assert_eq!(temperatures, vec![1.0, 2.0, 4.0]);
+
+        // a much less fit specimen practically never rises to the colder replica
+        let mut replicas = vec![replica(0.0, 1.0), replica(1000.0, 2.0)];
+        for iteration in (0..100).step_by(2) {
+            Metropolis::<Idle, Creature>::swap(&mut replicas, iteration, &config);
+            assert_eq!(energies(&replicas, &config), vec![0.0, 1000.0]);
+        }
+    }
 }
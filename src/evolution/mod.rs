@@ -2,43 +2,120 @@ use std::fmt;
 use std::fmt::Debug;
 use std::hash::Hash;
 
+use hashbrown::HashSet;
+use non_dominated_sort::DominanceOrd;
 use rand::Rng;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::configure::Config;
+use crate::configure::{Config, Distance, MutationBias};
 use crate::fitness::FitnessScore;
 use crate::util;
 use crate::util::count_min_sketch::Sketch;
 use crate::util::levy_flight::levy_decision;
 use crate::util::random::{hash_seed_rng, Prng};
 
-//pub mod lexicase;
+pub mod alps;
+pub mod lexicase;
+pub mod map_elites;
 pub mod metropolis;
 pub mod pareto_roulette;
 pub mod population;
+pub mod seed;
+pub mod sharing;
 pub mod tournament;
 
+/// A population under some selection scheme, which evolves one step at a
+/// time.
+pub trait World: Sized {
+    fn evolve(self) -> Self;
+
+    /// Evolves the world until it's time to stop.
+    fn run(mut self) {
+        while crate::keep_going() {
+            self = self.evolve();
+        }
+    }
+}
+
+/// A gene in a chromosome that has been rearranged by a structural
+/// mutation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gene<A> {
+    /// The gene at this index of the original chromosome, left as it was.
+    Kept(usize),
+    /// The gene at this index of the original chromosome, moved or copied
+    /// by the mutation.
+    Moved(usize),
+    /// A new allele, inserted by the mutation.
+    New(A),
+}
+
+/// The part a gene played the last time its lineage was executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum GeneRole {
+    /// The gene hasn't been executed, or its role couldn't be told.
+    Unknown,
+    /// The gene was executed as code.
+    Executed,
+    /// The gene was read as data by the code executed before it.
+    Consumed,
+    /// Execution never reached the gene.
+    Unexecuted,
+}
+
+impl Default for GeneRole {
+    fn default() -> Self {
+        Self::Unknown
+    }
+}
+
 pub trait Mutation {
     type Allele;
 
-    fn mutate_point(allele: &mut Self::Allele, config: &Config) -> Self;
+    fn mutate_point(allele: &mut Self::Allele, config: &Config, rng: &mut Prng) -> Self;
+
+    /// Mutations that change the shape of a chromosome, rather than a
+    /// single allele, by inserting, deleting or rearranging genes. Returns
+    /// the new arrangement of the genes, along with the mutation that
+    /// produced it, or `None` if the chromosome is to be left alone.
+    fn mutate_structure(
+        _chromosome: &[Self::Allele],
+        _config: &Config,
+        _rng: &mut Prng,
+    ) -> Option<(Vec<Gene<Self::Allele>>, Self)>
+    where
+        Self: Sized,
+    {
+        None
+    }
 
-    fn mutate(chromosome: &mut [Self::Allele], config: &Config) -> Vec<Option<Self>>
+    fn mutate(chromosome: &mut [Self::Allele], config: &Config, rng: &mut Prng) -> Vec<Option<Self>>
     where
         Self: Sized,
     {
-        let mut rng = rand::thread_rng();
-        let len = chromosome.len();
-        (0..len)
-            .map(|i| {
-                if levy_decision(&mut rng, len, config.mutation_exponent) {
-                    Some(Self::mutate_point(&mut chromosome[i], &config))
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<Option<Self>>>()
+        let positions = (0..chromosome.len()).collect::<Vec<usize>>();
+        Self::mutate_at(chromosome, &positions, config, rng)
+    }
+
+    /// Makes the levy-flight mutation decisions over the given positions
+    /// only, leaving the rest of the chromosome alone.
+    fn mutate_at(
+        chromosome: &mut [Self::Allele],
+        positions: &[usize],
+        config: &Config,
+        rng: &mut Prng,
+    ) -> Vec<Option<Self>>
+    where
+        Self: Sized,
+    {
+        let mut mutations = (0..chromosome.len()).map(|_| None).collect::<Vec<_>>();
+        for &i in positions {
+            if levy_decision(rng, positions.len(), config.mutation_exponent) {
+                mutations[i] = Some(Self::mutate_point(&mut chromosome[i], &config, rng));
+            }
+        }
+        mutations
     }
 }
 
@@ -49,6 +126,12 @@ pub struct LinearChromosome<
 > {
     pub chromosome: Vec<A>,
     pub mutations: Vec<Option<M>>,
+    /// The number of lineage generations since each gene was last executed.
+    /// Ages grow when genes are inherited, not while a creature survives.
+    pub ages: Vec<usize>,
+    /// The part each gene played the last time its lineage was executed,
+    /// which execution-guided mutation uses to choose its targets.
+    pub roles: Vec<GeneRole>,
     pub parentage: Vec<usize>,
     pub parent_names: Vec<String>,
     pub name: String,
@@ -66,37 +149,95 @@ impl<
         self.chromosome.len()
     }
 
-    pub fn crossover(parents: &[&Self], config: &Config) -> Self {
+    pub fn crossover(parents: &[&Self], config: &Config, rng: &mut Prng) -> Self {
         let min_mate_len = parents.iter().map(|p| p.len()).min().unwrap();
         let lambda = min_mate_len as f64 / config.crossover_period;
         let distribution =
             rand_distr::Exp::new(lambda).expect("Failed to create random distribution");
-        Self::crossover_by_distribution(&distribution, &parents)
+        let mut child = Self::crossover_by_distribution(&distribution, &parents, rng);
+        if let Some(ttl) = config.gene_ttl {
+            child.replace_expired_genes(ttl, config, rng);
+        }
+        child
+    }
+
+    /// Joins the first `prefix_len` genes of `prefix` to the genes of
+    /// `suffix` from `suffix_start` onwards. This lets genomes that know
+    /// where they can safely be cut choose their own crossover points.
+    pub fn splice(
+        prefix: &Self,
+        prefix_len: usize,
+        suffix: &Self,
+        suffix_start: usize,
+        config: &Config,
+        rng: &mut Prng,
+    ) -> Self {
+        let prefix_len = prefix_len.min(prefix.len());
+        let suffix_start = suffix_start.min(suffix.len());
+        let age = |parent: &Self, i: usize| parent.ages.get(i).map_or(1, |age| age + 1);
+        let chromosome = prefix.chromosome[..prefix_len]
+            .iter()
+            .chain(suffix.chromosome[suffix_start..].iter())
+            .cloned()
+            .collect::<Vec<A>>();
+        let ages = (0..prefix_len)
+            .map(|i| age(prefix, i))
+            .chain((suffix_start..suffix.len()).map(|i| age(suffix, i)))
+            .collect::<Vec<usize>>();
+        let role = |parent: &Self, i: usize| parent.roles.get(i).copied().unwrap_or_default();
+        let roles = (0..prefix_len)
+            .map(|i| role(prefix, i))
+            .chain((suffix_start..suffix.len()).map(|i| role(suffix, i)))
+            .collect::<Vec<GeneRole>>();
+        let len = chromosome.len();
+        let mut parentage = vec![0; prefix_len];
+        parentage.resize(len, 1);
+        let name = util::name::random(4, &chromosome);
+
+        let mut child = Self {
+            chromosome,
+            ages,
+            roles,
+            parentage,
+            mutations: vec![None; len],
+            parent_names: vec![prefix.name.clone(), suffix.name.clone()],
+            name,
+            generation: prefix.generation.max(suffix.generation) + 1,
+        };
+        if let Some(ttl) = config.gene_ttl {
+            child.replace_expired_genes(ttl, config, rng);
+        }
+        child
     }
 
     fn crossover_by_distribution<D: rand_distr::Distribution<f64>>(
         distribution: &D,
         parents: &[&Self],
+        rng: &mut Prng,
     ) -> Self {
         let mut chromosome = Vec::new();
+        let mut ages = Vec::new();
+        let mut roles = Vec::new();
         let mut parentage = Vec::new();
-        let mut rng = hash_seed_rng(&parents[0].chromosome);
         let mut ptrs = vec![0_usize; parents.len()];
         let switch = |rng: &mut Prng| rng.gen_range(0, parents.len());
         let sample = |rng: &mut Prng| distribution.sample(rng).round() as usize + 1;
 
         loop {
-            let src = switch(&mut rng);
+            let src = switch(rng);
             let take_from = ptrs[src];
 
             if take_from >= parents[src].len() {
                 break;
             }
-            //let take_to = std::cmp::min(ptrs[src] + sample(&mut rng), parents[src].len());
-            let take_to = ptrs[src] + sample(&mut rng);
+            //let take_to = std::cmp::min(ptrs[src] + sample(rng), parents[src].len());
+            let take_to = ptrs[src] + sample(rng);
             let len = parents[src].len();
             for i in take_from..take_to {
-                chromosome.push(parents[src].chromosome[i % len].clone())
+                chromosome.push(parents[src].chromosome[i % len].clone());
+                // genes inherit their age, and grow one generation older
+                ages.push(parents[src].ages.get(i % len).map_or(1, |age| age + 1));
+                roles.push(parents[src].roles.get(i % len).copied().unwrap_or_default());
             }
 
             for _ in 0..(take_to - take_from) {
@@ -107,7 +248,7 @@ impl<
             // now slide the other ptrs ahead a random interval
             for i in 0..ptrs.len() {
                 if i != src {
-                    ptrs[i] += sample(&mut rng);
+                    ptrs[i] += sample(rng);
                 }
             }
         }
@@ -117,6 +258,8 @@ impl<
 
         Self {
             chromosome,
+            ages,
+            roles,
             parentage,
             mutations: vec![None; len],
             parent_names: parents
@@ -128,10 +271,125 @@ impl<
         }
     }
 
-    pub fn mutate(&mut self, config: &Config) {
+    pub fn mutate(&mut self, config: &Config, rng: &mut Prng) {
         // maybe check a uniform mutation rate to see if any pointwise mutations happen at all.
-        let mutations = M::mutate(&mut self.chromosome, config);
+        let mutations = match self.mutation_targets(config.mutation_bias) {
+            Some(positions) => M::mutate_at(&mut self.chromosome, &positions, config, rng),
+            None => M::mutate(&mut self.chromosome, config, rng),
+        };
+        for (age, mutation) in self.ages.iter_mut().zip(mutations.iter()) {
+            if mutation.is_some() {
+                *age = 0;
+            }
+        }
+        self.mutations = mutations;
+        if let Some((genes, mutation)) = M::mutate_structure(&self.chromosome, config, rng) {
+            self.rearrange(genes, mutation);
+        }
+    }
+
+    pub fn mutated_roles(&self) -> Vec<GeneRole> {
+        self.mutations
+            .iter()
+            .zip(self.roles.iter())
+            .filter(|(m, _)| m.is_some())
+            .map(|(_, r)| *r)
+            .collect()
+    }
+
+    /// The positions that the mutation bias would confine mutation to, or
+    /// `None` if mutation should range over the whole chromosome, either
+    /// because there's no bias or because the genes' roles don't tell us
+    /// where to aim.
+    fn mutation_targets(&self, bias: MutationBias) -> Option<Vec<usize>> {
+        let positions_where = |pred: &dyn Fn(GeneRole) -> bool| {
+            (0..self.roles.len())
+                .filter(|i| pred(self.roles[*i]))
+                .collect::<Vec<usize>>()
+        };
+        let targets = match bias {
+            MutationBias::Uniform => return None,
+            MutationBias::FirstUnexecuted => positions_where(&|r| r == GeneRole::Unexecuted)
+                .into_iter()
+                .take(1)
+                .collect(),
+            MutationBias::ProtectExecuted => {
+                positions_where(&|r| r != GeneRole::Executed && r != GeneRole::Consumed)
+            }
+            MutationBias::Consumed => positions_where(&|r| r == GeneRole::Consumed),
+        };
+        if targets.is_empty() || self.roles.len() != self.chromosome.len() {
+            None
+        } else {
+            Some(targets)
+        }
+    }
+
+    /// Rebuilds the chromosome, along with the records kept for each of
+    /// its genes, to follow a structural mutation. Moved and new genes are
+    /// marked with the mutation, and their ages reset.
+    fn rearrange(&mut self, genes: Vec<Gene<A>>, mutation: M) {
+        let len = genes.len();
+        let mut chromosome = Vec::with_capacity(len);
+        let mut mutations = Vec::with_capacity(len);
+        let mut ages = Vec::with_capacity(len);
+        let mut roles = Vec::with_capacity(len);
+        let mut parentage = Vec::with_capacity(len);
+        for gene in genes {
+            let source = match gene {
+                Gene::Kept(i) => {
+                    chromosome.push(self.chromosome[i].clone());
+                    mutations.push(self.mutations.get(i).cloned().flatten());
+                    ages.push(self.ages.get(i).copied().unwrap_or(0));
+                    roles.push(self.roles.get(i).copied().unwrap_or_default());
+                    self.parentage.get(i).copied()
+                }
+                Gene::Moved(i) => {
+                    chromosome.push(self.chromosome[i].clone());
+                    mutations.push(Some(mutation.clone()));
+                    ages.push(0);
+                    roles.push(self.roles.get(i).copied().unwrap_or_default());
+                    self.parentage.get(i).copied()
+                }
+                Gene::New(allele) => {
+                    chromosome.push(allele);
+                    mutations.push(Some(mutation.clone()));
+                    ages.push(0);
+                    roles.push(GeneRole::Unknown);
+                    // credit new genes to the parent of the gene before them
+                    parentage.last().copied()
+                }
+            };
+            if !self.parentage.is_empty() {
+                parentage.push(source.unwrap_or(0));
+            }
+        }
+        self.chromosome = chromosome;
         self.mutations = mutations;
+        self.ages = ages;
+        self.roles = roles;
+        self.parentage = parentage;
+    }
+
+    /// Resets the age of every gene for which `executed` holds.
+    pub fn refresh_genes<F: Fn(&A) -> bool>(&mut self, executed: F) {
+        for (allele, age) in self.chromosome.iter().zip(self.ages.iter_mut()) {
+            if executed(allele) {
+                *age = 0;
+            }
+        }
+    }
+
+    /// Replaces every gene that has been inherited `ttl` times without being
+    /// executed with a point mutation of itself.
+    fn replace_expired_genes(&mut self, ttl: usize, config: &Config, rng: &mut Prng) {
+        for i in 0..self.chromosome.len() {
+            if self.ages[i] >= ttl {
+                let mutation = M::mutate_point(&mut self.chromosome[i], config, rng);
+                self.mutations[i] = Some(mutation);
+                self.ages[i] = 0;
+            }
+        }
     }
 }
 
@@ -170,7 +428,7 @@ where
 }
 
 pub trait Genome: Hash {
-    type Allele: Clone + Debug + PartialEq + Eq + Hash + Serialize + Sized;
+    type Allele: Clone + Debug + PartialEq + Eq + Hash + Serialize + DeserializeOwned + Sized;
 
     fn chromosome(&self) -> &[Self::Allele];
 
@@ -184,7 +442,23 @@ pub trait Genome: Hash {
     where
         Self: Sized;
 
-    fn crossover(parents: &[&Self], config: &Config) -> Self
+    /// Builds a creature around a chromosome taken from a seed source (see
+    /// the `seed` module), or returns `None` if this kind of genome can't
+    /// be seeded.
+    fn from_chromosome<H: Hash>(
+        _chromosome: Vec<Self::Allele>,
+        _config: &Config,
+        _salt: H,
+    ) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Every random choice made in crossover and mutation is drawn from
+    /// the `rng` passed in, so that a run can be reproduced from its seed.
+    fn crossover(parents: &[&Self], config: &Config, rng: &mut Prng) -> Self
     where
         Self: Sized;
 
@@ -229,18 +503,20 @@ pub trait Genome: Hash {
         (chromosome, parentage)
     }
 
-    fn mutate(&mut self, config: &Config);
+    fn mutate(&mut self, config: &Config, rng: &mut Prng);
 
-    fn mate(parents: &[&Self], config: &Config) -> Self
+    /// The `salt` tells apart offspring of the same parents, which would
+    /// otherwise be identical.
+    fn mate<H: Hash>(parents: &[&Self], config: &Config, salt: H) -> Self
     where
         Self: Sized,
     {
-        let mut child = Self::crossover(parents, config);
+        let mut rng = hash_seed_rng(&(parents, salt));
+        let mut child = Self::crossover(parents, config, &mut rng);
         // the mutate method should check the mutation rate or exponent and
         // make the mutation decisions internally
-        let mut rng = hash_seed_rng(&parents);
         if rng.gen_range(0.0, 1.0) < config.mutation_rate {
-            child.mutate(&config);
+            child.mutate(&config, &mut rng);
         }
         child
     }
@@ -284,6 +560,38 @@ pub trait Genome: Hash {
     }
 
     fn incr_num_offspring(&mut self, _n: usize);
+
+    /// The number of generations since the creature's oldest line of
+    /// descent was randomly generated. Serves as its age, in ALPS.
+    fn generation(&self) -> usize {
+        unimplemented!("implement as needed (for ALPS, e.g.)")
+    }
+
+    /// The number of generations since each gene was last executed, for
+    /// genomes that keep track of this.
+    fn gene_ages(&self) -> Option<&[usize]> {
+        None
+    }
+
+    /// The roles that the genes changed by the last mutation went on to
+    /// play, for genomes that keep track of this.
+    fn mutated_roles(&self) -> Vec<GeneRole> {
+        vec![]
+    }
+}
+
+/// Orders phenomes by their fitness, whatever its representation, so that
+/// they can be sorted into non-dominated fronts. Pairs that can't be
+/// compared are treated as equal.
+#[derive(Clone, Debug, Copy)]
+pub struct FitnessDominanceOrd;
+
+impl<P: Phenome> DominanceOrd<P> for FitnessDominanceOrd {
+    fn dominance_ord(&self, a: &P, b: &P) -> std::cmp::Ordering {
+        a.fitness()
+            .partial_cmp(&b.fitness())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }
 }
 
 pub trait Phenome: Clone + Debug + Send + Serialize + Hash {
@@ -327,11 +635,220 @@ pub trait Phenome: Clone + Debug + Send + Serialize + Hash {
 
     fn is_goal_reached(&self, config: &Config) -> bool;
 
+    /// Whether the phenotype meets the completion criterion of the
+    /// current stage of a staged fitness configuration.
+    fn is_stage_complete(&self, config: &Config) -> bool {
+        self.scalar_fitness(config.fitness.priority())
+            .map(|p| p - config.fitness.target() <= std::f64::EPSILON)
+            .unwrap_or(false)
+    }
+
     fn fails(&self, _problem: &Self::Problem) -> bool {
         unimplemented!("implement as needed (for lexicase, e.g.)");
     }
 
+    /// The error on a single fitness case, where 0.0 is a pass. Needed
+    /// by epsilon-lexicase selection; by default, a failed case simply
+    /// counts as an error of 1.0.
+    fn case_error(&self, problem: &Self::Problem) -> f64 {
+        if self.fails(problem) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
     fn mature(&self) -> bool {
         unimplemented!("implement as needed")
     }
+
+    /// The distance between two phenotypes by some behavioural measure,
+    /// from 0.0 (identical) to 1.0. Needed only for fitness sharing.
+    fn behavioural_distance(&self, _other: &Self, _measure: Distance) -> f64 {
+        unimplemented!("implement as needed (for fitness sharing, e.g.)")
+    }
+
+    /// The addresses of the gadgets executed in the phenotype's development,
+    /// for phenotypes that have them. Needed for the "UniqueGadgets"
+    /// diversity measure.
+    fn gadgets_executed(&self) -> Option<&HashSet<u64>> {
+        None
+    }
+
+    /// Discards the results of development, so that the phenotype will be
+    /// developed again. Only needed for `fitness.re_execute`.
+    fn clear_development(&mut self) {}
+
+    /// Whether the fitness function needs to be applied to the phenotype:
+    /// either because it hasn't been yet, because it was scored under an
+    /// earlier stage, or because, in dynamic mode, its fitness is older
+    /// than `fitness.reevaluate_every` epochs.
+    fn is_fitness_stale(&self, config: &Config) -> bool {
+        match self.fitness() {
+            None => true,
+            Some(fitness)
+                if fitness
+                    .stage()
+                    .map_or(false, |stage| stage != config.fitness.stage()) =>
+            {
+                true
+            }
+            Some(_) if !config.fitness.dynamic => false,
+            Some(fitness) => fitness.epoch().map_or(true, |epoch| {
+                crate::get_epoch_counter() >= epoch + config.fitness.reevaluate_every
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+
+    use crate::configure::StageConfig;
+    use crate::examples::linear_gp::Creature;
+    use crate::fitness::Weighted;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Hash, PartialEq, Serialize, Deserialize)]
+    struct Flip;
+
+    impl Mutation for Flip {
+        type Allele = u64;
+
+        fn mutate_point(allele: &mut u64, _config: &Config, _rng: &mut Prng) -> Self {
+            *allele = !*allele;
+            Flip
+        }
+    }
+
+    fn linear_chromosome(
+        chromosome: Vec<u64>,
+        ages: Vec<usize>,
+        roles: Vec<GeneRole>,
+        generation: usize,
+    ) -> LinearChromosome<u64, Flip> {
+        let len = chromosome.len();
+        LinearChromosome {
+            name: util::name::random(4, &chromosome),
+            chromosome,
+            mutations: vec![None; len],
+            ages,
+            roles,
+            parentage: vec![],
+            parent_names: vec![],
+            generation,
+        }
+    }
+
+    #[test]
+    fn test_splice() {
+        use GeneRole::*;
+        let config = Config::default();
+        let mut rng = hash_seed_rng(&0);
+        let prefix = linear_chromosome(
+            vec![1, 2, 3],
+            vec![0, 4, 7],
+            vec![Executed, Consumed, Unexecuted],
+            2,
+        );
+        let suffix = linear_chromosome(
+            vec![10, 20, 30, 40],
+            vec![1, 1, 2, 3],
+            vec![Unknown, Executed, Consumed, Unexecuted],
+            5,
+        );
+
+        let child = LinearChromosome::splice(&prefix, 2, &suffix, 1, &config, &mut rng);
+        assert_eq!(child.chromosome, vec![1, 2, 20, 30, 40]);
+        // inherited genes grow one generation older
+        assert_eq!(child.ages, vec![1, 5, 2, 3, 4]);
+        assert_eq!(
+            child.roles,
+            vec![Executed, Consumed, Executed, Consumed, Unexecuted]
+        );
+        assert_eq!(child.parentage, vec![0, 0, 1, 1, 1]);
+        assert_eq!(child.mutations, vec![None; 5]);
+        assert_eq!(
+            child.parent_names,
+            vec![prefix.name.clone(), suffix.name.clone()]
+        );
+        assert_eq!(child.generation, 6);
+
+        // out of range cut points are clamped to the parents' lengths
+        let child = LinearChromosome::splice(&prefix, 10, &suffix, 10, &config, &mut rng);
+        assert_eq!(child.chromosome, prefix.chromosome);
+        assert_eq!(child.ages, vec![1, 5, 8]);
+        assert_eq!(child.roles, prefix.roles);
+        assert_eq!(child.parentage, vec![0, 0, 0]);
+
+        let child = LinearChromosome::splice(&prefix, 0, &suffix, 0, &config, &mut rng);
+        assert_eq!(child.chromosome, suffix.chromosome);
+        assert_eq!(child.parentage, vec![1, 1, 1, 1]);
+
+        // genes without a recorded age or role are treated as newborn
+        let unrecorded = linear_chromosome(vec![5, 6], vec![], vec![], 0);
+        let child = LinearChromosome::splice(&unrecorded, 2, &suffix, 3, &config, &mut rng);
+        assert_eq!(child.chromosome, vec![5, 6, 40]);
+        assert_eq!(child.ages, vec![1, 1, 4]);
+        let unknown = GeneRole::default();
+        assert_eq!(child.roles, vec![unknown, unknown, Unexecuted]);
+    }
+
+    #[test]
+    fn test_rearrange() {
+        use GeneRole::*;
+        let mut chromosome = linear_chromosome(
+            vec![1, 2, 3],
+            vec![3, 4, 5],
+            vec![Executed, Consumed, Unexecuted],
+            0,
+        );
+        chromosome.mutations = vec![Some(Flip), None, None];
+        chromosome.parentage = vec![0, 1, 1];
+
+        chromosome.rearrange(
+            vec![Gene::Kept(0), Gene::New(9), Gene::Moved(2), Gene::Kept(1)],
+            Flip,
+        );
+        assert_eq!(chromosome.chromosome, vec![1, 9, 3, 2]);
+        // kept genes hold on to their records, while moved and new genes
+        // are marked with the mutation and made newborn
+        assert_eq!(
+            chromosome.mutations,
+            vec![Some(Flip), Some(Flip), Some(Flip), None]
+        );
+        assert_eq!(chromosome.ages, vec![3, 0, 0, 4]);
+        assert_eq!(
+            chromosome.roles,
+            vec![Executed, Unknown, Unexecuted, Consumed]
+        );
+        // the new gene is credited to the parent of the gene before it
+        assert_eq!(chromosome.parentage, vec![0, 0, 1, 1]);
+
+        // a chromosome without parentage is left without it
+        let mut chromosome = linear_chromosome(vec![1, 2], vec![0, 0], vec![], 0);
+        chromosome.rearrange(vec![Gene::Moved(1), Gene::Moved(0)], Flip);
+        assert_eq!(chromosome.chromosome, vec![2, 1]);
+        assert!(chromosome.parentage.is_empty());
+        assert_eq!(chromosome.roles, vec![Unknown, Unknown]);
+    }
+
+    #[test]
+    fn test_fitness_goes_stale_when_stage_advances() {
+        let mut config = Config::default();
+        config.fitness.stages = vec![StageConfig::default()];
+        let mut creature = Creature::default();
+        assert!(creature.is_fitness_stale(&config));
+
+        creature.set_fitness(Weighted::from_config(&config.fitness));
+        assert!(!creature.is_fitness_stale(&config));
+
+        config.fitness.advance_stage();
+        assert!(creature.is_fitness_stale(&config));
+
+        creature.set_fitness(Weighted::from_config(&config.fitness));
+        assert!(!creature.is_fitness_stale(&config));
+    }
 }
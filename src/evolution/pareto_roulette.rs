@@ -7,7 +7,8 @@ use rand_distr::Distribution;
 use non_dominated_sort::{non_dominated_sort, DominanceOrd};
 
 use crate::configure::Config;
-use crate::evolution::{Genome, Phenome};
+use crate::evolution::seed::initial_population;
+use crate::evolution::{Genome, Phenome, World};
 use crate::increment_epoch_counter;
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
@@ -24,9 +25,7 @@ pub struct Roulette<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOr
 
 impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<E, P, D> {
     pub fn new(config: &Config, observer: Observer<P>, evaluator: E, dominance_order: D) -> Self {
-        let population = (0..config.pop_size)
-            .map(|i| P::random(&config, i))
-            .collect();
+        let population = initial_population(config, config.pop_size, |i| P::random(config, i));
 
         Self {
             population,
@@ -50,10 +49,13 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
 
         let mut rng = hash_seed_rng(&population);
         // measure and assign fitness scores to entire population
+        let (stale, current): (Vec<P>, Vec<P>) = population
+            .into_iter()
+            .partition(|p| p.is_fitness_stale(&config));
         let mut population = evaluator
-            .development_pipeline(population.into_iter())
+            .evaluate(stale, &config)
             .into_iter()
-            .map(|p| evaluator.apply_fitness_function(p))
+            .chain(current.into_iter())
             .collect::<Vec<P>>();
         // we're going to need to clone the population to send to the observer, anyway
         // so we might as well do that now. this lets us get around certain awkward
@@ -124,7 +126,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
             //     .map(|()| all_parents[rng.gen::<usize>() % all_parents.len()])
             //     .collect::<Vec<&P>>();
 
-            let child: P = Genome::mate(&parents, &config);
+            let child: P = Genome::mate(&parents, &config, (iteration, new_population.len()));
             new_population.push(child)
         }
 
@@ -140,3 +142,9 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
         }
     }
 }
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> World for Roulette<E, P, D> {
+    fn evolve(self) -> Self {
+        Roulette::evolve(self)
+    }
+}
@@ -1,52 +1,346 @@
+use std::fs;
 use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Mutex;
 
 use crossbeam::queue::SegQueue;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Serialize;
 
-pub struct Pier<P> {
-    capacity: usize,
+use crate::configure::{Config, EmigrantPolicy, MigrationConfig, ReplacementPolicy, Topology};
+use crate::evolution::{sharing, Genome, Phenome};
+use crate::util::random::hash_seed_rng;
+
+/// A one-way route from one island to another, holding the creatures in
+/// transit.
+struct Edge<P> {
+    from: usize,
+    to: usize,
     count: AtomicUsize,
     q: SegQueue<P>,
 }
 
+#[derive(Serialize)]
+struct MigrationRecord<'a> {
+    epoch: usize,
+    from: usize,
+    to: usize,
+    name: &'a str,
+}
+
+/// The network of routes along which creatures migrate between islands.
+/// Each edge of the topology has its own queue, so an island only ever
+/// receives creatures from its neighbours.
+pub struct Pier<P> {
+    capacity: usize,
+    edges: Vec<Edge<P>>,
+    outgoing: Vec<Vec<usize>>,
+    incoming: Vec<Vec<usize>>,
+    migration: MigrationConfig,
+    log: Option<Mutex<csv::Writer<fs::File>>>,
+}
+
+/// The directed edges of the topology, over `n` islands, without
+/// duplicates or self-loops.
+fn topology_edges(topology: Topology, n: usize, seed: u64) -> Vec<(usize, usize)> {
+    let mut edges = match topology {
+        Topology::Ring => (0..n)
+            .flat_map(|i| vec![(i, (i + 1) % n), ((i + 1) % n, i)])
+            .collect::<Vec<_>>(),
+        Topology::Torus => {
+            let width = ((n as f64).sqrt().floor() as usize).max(1);
+            (0..n)
+                .flat_map(|i| {
+                    let row = (i / width) * width;
+                    let row_len = width.min(n - row);
+                    vec![
+                        (i, row + (i - row + 1) % row_len),
+                        (i, row + (i - row + row_len - 1) % row_len),
+                        (i, (i + width) % n),
+                        (i, (i + n - width % n) % n),
+                    ]
+                })
+                .collect::<Vec<_>>()
+        }
+        Topology::Star => (1..n).flat_map(|i| vec![(0, i), (i, 0)]).collect(),
+        Topology::Full => (0..n).flat_map(|i| (0..n).map(move |j| (i, j))).collect(),
+        Topology::RandomRegular(degree) => {
+            // The union of `degree` random cycles through every island
+            // gives each island that many neighbours in each direction,
+            // less any duplicates.
+            let mut rng = hash_seed_rng(&seed);
+            let mut islands = (0..n).collect::<Vec<usize>>();
+            let mut edges = Vec::new();
+            for _ in 0..degree {
+                islands.shuffle(&mut rng);
+                for i in 0..n {
+                    edges.push((islands[i], islands[(i + 1) % n]));
+                }
+            }
+            edges
+        }
+    };
+    edges.retain(|(from, to)| from != to);
+    edges.sort_unstable();
+    edges.dedup();
+    edges
+}
+
 impl<P> Pier<P> {
-    pub fn new(capacity: usize) -> Self {
+    pub fn new(config: &Config) -> Self {
+        let n = config.num_islands;
+        let migration = config.migration.clone();
+        let edges = topology_edges(migration.topology, n, config.random_seed)
+            .into_iter()
+            .map(|(from, to)| Edge {
+                from,
+                to,
+                count: AtomicUsize::new(0),
+                q: SegQueue::new(),
+            })
+            .collect::<Vec<_>>();
+        let mut outgoing = vec![vec![]; n];
+        let mut incoming = vec![vec![]; n];
+        for (i, edge) in edges.iter().enumerate() {
+            outgoing[edge.from].push(i);
+            incoming[edge.to].push(i);
+        }
+        log::info!(
+            "{:?} migration topology over {} islands: {:?}",
+            migration.topology,
+            n,
+            edges.iter().map(|e| (e.from, e.to)).collect::<Vec<_>>()
+        );
+        // worlds that don't migrate are still handed a pier, but needn't
+        // leave an empty log behind
+        let log = if migration.log && config.selection.migrates() {
+            Self::open_log(config)
+        } else {
+            None
+        };
         Self {
-            capacity,
-            count: AtomicUsize::new(0),
-            q: SegQueue::new(),
+            capacity: migration.edge_capacity,
+            edges,
+            outgoing,
+            incoming,
+            migration,
+            log,
+        }
+    }
+
+    fn open_log(config: &Config) -> Option<Mutex<csv::Writer<fs::File>>> {
+        let dir = config.population_directory();
+        if dir.is_empty() {
+            return None;
+        }
+        let path = format!("{}/migration.csv", dir);
+        let add_headers = !std::path::Path::new(&path).exists();
+        fs::create_dir_all(dir)
+            .and_then(|()| fs::OpenOptions::new().create(true).append(true).open(&path))
+            .map_err(|e| log::error!("Error opening migration log at {}: {:?}", path, e))
+            .ok()
+            .map(|file| {
+                Mutex::new(
+                    csv::WriterBuilder::new()
+                        .delimiter(b',')
+                        .terminator(csv::Terminator::Any(b'\n'))
+                        .has_headers(add_headers)
+                        .from_writer(file),
+                )
+            })
+    }
+
+    /// Sends the emigrant along a randomly chosen edge leading out of
+    /// the island, if any of them has room for it.
+    pub fn embark<R: Rng>(&self, from: usize, emigrant: P, rng: &mut R) -> Result<(), P> {
+        let mut routes = self.outgoing.get(from).cloned().unwrap_or_default();
+        routes.shuffle(rng);
+        for i in routes {
+            let edge = &self.edges[i];
+            if edge.count.load(atomic::Ordering::SeqCst) >= self.capacity {
+                continue;
+            }
+            edge.q.push(emigrant);
+            let len = edge.count.fetch_add(1, atomic::Ordering::SeqCst);
+            log::debug!(
+                "Emigrant embarked onto pier from {} to {}. Holding {}",
+                edge.from,
+                edge.to,
+                len + 1
+            );
+            return Ok(());
         }
+        log::debug!("Pier at capacity, returning emigrant");
+        Err(emigrant)
     }
+}
 
-    pub fn len(&self) -> usize {
-        self.count.load(atomic::Ordering::SeqCst)
+impl<P: Phenome> Pier<P> {
+    /// Takes a creature from a randomly chosen edge leading into the
+    /// island, if any of them holds one.
+    pub fn disembark<R: Rng>(&self, to: usize, rng: &mut R) -> Option<P> {
+        let mut routes = self.incoming.get(to).cloned().unwrap_or_default();
+        routes.shuffle(rng);
+        for i in routes {
+            let edge = &self.edges[i];
+            if let Ok(p) = edge.q.pop() {
+                let len = edge.count.fetch_sub(1, atomic::Ordering::SeqCst);
+                log::debug!(
+                    "Immigrant disembarked from pier from {} to {}. Holding {}",
+                    edge.from,
+                    edge.to,
+                    len - 1
+                );
+                self.log_migration(edge, &p);
+                return Some(p);
+            }
+        }
+        None
     }
 
-    fn incr_count(&self) -> usize {
-        self.count.fetch_add(1, atomic::Ordering::SeqCst)
+    fn log_migration(&self, edge: &Edge<P>, immigrant: &P) {
+        if let Some(ref log) = self.log {
+            let record = MigrationRecord {
+                epoch: crate::get_epoch_counter(),
+                from: edge.from,
+                to: edge.to,
+                name: immigrant.name(),
+            };
+            let mut writer = log.lock().expect("poisoned lock on migration log");
+            if let Err(e) = writer.serialize(record) {
+                log::error!("Error logging migration: {:?}", e);
+            } else if let Err(e) = writer.flush() {
+                log::error!("Error flushing migration log: {:?}", e);
+            }
+        }
     }
+}
 
-    fn decr_count(&self) -> usize {
-        self.count.fetch_sub(1, atomic::Ordering::SeqCst)
+impl<P: Phenome + Genome> Pier<P> {
+    /// Carries out a migration for the island, according to the
+    /// configured policies: with probability `tournament.migration_rate`,
+    /// one of the survivors of a tournament, which should be ranked from
+    /// best to worst, emigrates. Otherwise, an immigrant, if one is
+    /// waiting, arrives among them.
+    pub fn exchange<R: Rng>(&self, survivors: &mut Vec<P>, config: &Config, rng: &mut R) {
+        if survivors.is_empty() {
+            return;
+        }
+        let island = config.island_identifier;
+        let mut migrated = false;
+        if rng.gen_range(0.0, 1.0) < config.tournament.migration_rate {
+            log::debug!("Attempting migration...");
+            let i = self.choose_emigrant(survivors, rng);
+            if let ReplacementPolicy::Join = self.migration.replacement {
+                let emigrant = survivors.remove(i);
+                if let Err(emigrant) = self.embark(island, emigrant, rng) {
+                    log::debug!("Pier full, returning emigrant to population");
+                    survivors.insert(i, emigrant);
+                } else {
+                    migrated = true;
+                }
+            } else {
+                migrated = self.embark(island, survivors[i].clone(), rng).is_ok();
+            }
+        }
+        if !migrated {
+            if let Some(immigrant) = self.disembark(island, rng) {
+                log::debug!(
+                    "{} has arrived from the pier of island {}",
+                    immigrant.name(),
+                    island
+                );
+                match self.migration.replacement {
+                    ReplacementPolicy::Join => survivors.push(immigrant),
+                    ReplacementPolicy::Worst => {
+                        let last = survivors.len() - 1;
+                        survivors[last] = immigrant;
+                    }
+                    ReplacementPolicy::Random => {
+                        let i = rng.gen_range(0, survivors.len());
+                        survivors[i] = immigrant;
+                    }
+                }
+            }
+        }
     }
 
-    pub fn embark(&self, emigrant: P) -> Result<(), P> {
-        if self.len() >= self.capacity {
-            log::debug!("Pier at capacity, returning emigrant");
-            return Err(emigrant);
+    fn choose_emigrant<R: Rng>(&self, survivors: &[P], rng: &mut R) -> usize {
+        match self.migration.emigrant {
+            EmigrantPolicy::Best => 0,
+            EmigrantPolicy::Worst => survivors.len() - 1,
+            EmigrantPolicy::Random => rng.gen_range(0, survivors.len()),
+            EmigrantPolicy::MostNovel => {
+                let distance = self.migration.distance;
+                (0..survivors.len())
+                    .map(|i| {
+                        let total = survivors
+                            .iter()
+                            .map(|other| sharing::distance(&survivors[i], other, distance))
+                            .sum::<f64>();
+                        (i, total)
+                    })
+                    .fold((0, std::f64::MIN), |best, (i, total)| {
+                        if total > best.1 {
+                            (i, total)
+                        } else {
+                            best
+                        }
+                    })
+                    .0
+            }
         }
-        self.q.push(emigrant);
-        let len = self.incr_count();
-        log::debug!("Emigrant embarked onto pier. Holding {}", len + 1);
-        Ok(())
     }
+}
 
-    pub fn disembark(&self) -> Option<P> {
-        if let Ok(p) = self.q.pop() {
-            let len = self.decr_count();
-            log::debug!("Immigrant disembarked from pier. Holding {}", len - 1);
-            Some(p)
-        } else {
-            None
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn degrees(edges: &[(usize, usize)], n: usize) -> (Vec<usize>, Vec<usize>) {
+        let mut out_degree = vec![0; n];
+        let mut in_degree = vec![0; n];
+        for (from, to) in edges {
+            out_degree[*from] += 1;
+            in_degree[*to] += 1;
         }
+        (out_degree, in_degree)
+    }
+
+    #[test]
+    fn test_topology_edges() {
+        let n = 16;
+        let (out_degree, in_degree) = degrees(&topology_edges(Topology::Ring, n, 0), n);
+        assert!(out_degree.iter().chain(in_degree.iter()).all(|d| *d == 2));
+
+        let (out_degree, in_degree) = degrees(&topology_edges(Topology::Torus, n, 0), n);
+        assert!(out_degree.iter().chain(in_degree.iter()).all(|d| *d == 4));
+
+        // a torus two islands wide has only one neighbour along each row
+        let edges = topology_edges(Topology::Torus, 6, 0);
+        let (out_degree, in_degree) = degrees(&edges, 6);
+        assert!(out_degree.iter().chain(in_degree.iter()).all(|d| *d == 3));
+        assert!(edges.iter().all(|(from, to)| edges.contains(&(*to, *from))));
+
+        let (out_degree, in_degree) = degrees(&topology_edges(Topology::Torus, 12, 0), 12);
+        assert!(out_degree.iter().chain(in_degree.iter()).all(|d| *d == 4));
+
+        let (out_degree, in_degree) = degrees(&topology_edges(Topology::Star, n, 0), n);
+        assert_eq!(out_degree[0], n - 1);
+        assert_eq!(in_degree[0], n - 1);
+        assert!(out_degree[1..].iter().all(|d| *d == 1));
+
+        let (out_degree, _) = degrees(&topology_edges(Topology::Full, n, 0), n);
+        assert!(out_degree.iter().all(|d| *d == n - 1));
+
+        let edges = topology_edges(Topology::RandomRegular(3), n, 0xdead_beef);
+        let (out_degree, in_degree) = degrees(&edges, n);
+        assert!(out_degree
+            .iter()
+            .chain(in_degree.iter())
+            .all(|d| *d >= 1 && *d <= 3));
+        assert!(edges.iter().all(|(from, to)| from != to));
+
+        assert!(topology_edges(Topology::Full, 1, 0).is_empty());
     }
 }
@@ -37,6 +37,10 @@ impl<P: Hash> TrivialGeography<P> {
         self.radius = radius.min(self.len())
     }
 
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+
     pub fn len(&self) -> usize {
         self.deme.len() - self.vacancies.len()
     }
@@ -99,13 +103,42 @@ impl<P: Hash> TrivialGeography<P> {
     }
 
     pub fn choose_combatants<R: Rng>(&mut self, n: usize, rng: &mut R) -> Vec<P> {
+        self.choose_combatants_and_range(n, rng).0
+    }
+
+    /// Like `choose_combatants`, but also returns the range of indices the
+    /// combatants were drawn from, so that the rest of the neighbourhood
+    /// can be inspected with `get`.
+    pub fn choose_combatants_and_range<R: Rng>(
+        &mut self,
+        n: usize,
+        rng: &mut R,
+    ) -> (Vec<P>, Vec<usize>) {
         debug_assert!(
             n < self.radius,
             "don't try to take more creatures than the radius allows"
         );
 
         let range = self.get_range(rng);
-        self.choose_with_range(&range, n, rng)
+        let combatants = self.choose_with_range(&range, n, rng);
+        (combatants, range)
+    }
+
+    /// Removes the cells left empty by creatures that were extracted and
+    /// never replaced, so that the deme can shrink, and narrows the radius
+    /// if it's now wider than the deme.
+    pub fn compact(&mut self) {
+        if self.vacancies.is_empty() {
+            return;
+        }
+        self.deme.retain(Option::is_some);
+        self.vacancies.clear();
+        self.radius = self.radius.min(self.len());
+    }
+
+    /// Returns the creature at the given index, if that cell is occupied.
+    pub fn get(&self, index: usize) -> Option<&P> {
+        self.deme.get(index).and_then(Option::as_ref)
     }
 
     #[allow(dead_code)]
@@ -147,6 +180,15 @@ impl<P: Hash> FromIterator<P> for TrivialGeography<P> {
     }
 }
 
+impl<P: Hash> IntoIterator for TrivialGeography<P> {
+    type Item = P;
+    type IntoIter = std::iter::Flatten<std::vec::IntoIter<Option<P>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.deme.into_iter().flatten()
+    }
+}
+
 impl<P: Hash + Send> FromParallelIterator<P> for TrivialGeography<P> {
     fn from_par_iter<I>(par_iter: I) -> Self
     where
@@ -258,4 +300,26 @@ mod test {
             assert_eq!((i, n), (i, m), "Duplicates returned by choose_multiple!");
         }
     }
+
+    #[test]
+    fn test_compact() {
+        let mut geo: TrivialGeography<usize> = (0..16).collect();
+        geo.set_radius(12);
+        let mut rng = hash_seed_rng(&0xbeef);
+        let combatants = geo.choose_combatants(6, &mut rng);
+        assert_eq!(geo.len(), 10);
+        // only half of them come back
+        for c in combatants.into_iter().take(3) {
+            geo.insert(c).unwrap();
+        }
+        geo.compact();
+        assert_eq!(geo.len(), 13);
+        assert_eq!(geo.deme.len(), 13);
+        assert!(geo.deme.iter().all(Option::is_some));
+        assert_eq!(geo.radius, 12);
+        let _ = geo.choose_combatants(5, &mut rng);
+        geo.compact();
+        assert_eq!(geo.len(), 8);
+        assert_eq!(geo.radius, 8);
+    }
 }
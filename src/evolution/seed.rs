@@ -0,0 +1,267 @@
+//! Seeding the initial population with chromosomes from elsewhere: the
+//! populations and champions dumped by earlier runs, or chains written by
+//! hand. See `configure::SeedingConfig`.
+//!
+//! Dumped creatures are read as plain JSON, and only their chromosomes are
+//! kept, so that a seed carries nothing over from the run it came from but
+//! its genes. The chromosome is taken to be the creature's `chromosome`
+//! field, or, if that's a `LinearChromosome`, its own `chromosome` field.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rand::seq::SliceRandom;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::configure::{Config, SeedSource};
+use crate::error::Error;
+use crate::evolution::Genome;
+use crate::util::dump::undump;
+use crate::util::random::hash_seed_rng;
+
+/// Builds a population of `size` creatures, the configured proportion of
+/// which are seeded, and the rest of which are made by `random`, which is
+/// passed each creature's index. The seeds are shuffled in among the
+/// random creatures.
+pub fn initial_population<P, F>(config: &Config, size: usize, random: F) -> Vec<P>
+where
+    P: Genome + Send,
+    F: Fn(usize) -> P + Send + Sync,
+{
+    let chromosomes = load_chromosomes::<P::Allele>(config);
+    let quota = if chromosomes.is_empty() {
+        0
+    } else {
+        ((config.seeding.proportion * size as f64).round() as usize).min(size)
+    };
+    let mut population = (0..quota)
+        .filter_map(|i| {
+            let chromosome = chromosomes[i % chromosomes.len()].clone();
+            P::from_chromosome(chromosome, config, ("seed", i))
+        })
+        .collect::<Vec<P>>();
+    if quota > 0 {
+        if population.is_empty() {
+            log::warn!("Seeds were found, but this kind of creature can't be seeded");
+        } else {
+            log::info!(
+                "Seeded {} of {} creatures from {} chromosomes",
+                population.len(),
+                size,
+                chromosomes.len()
+            );
+        }
+    }
+    let seeded = population.len();
+    population.par_extend((seeded..size).into_par_iter().map(random));
+    if seeded > 0 {
+        // scatter the seeds, so that they don't all end up in one
+        // neighbourhood of a spatially structured population
+        population.shuffle(&mut hash_seed_rng(&(
+            config.random_seed,
+            config.island_identifier,
+        )));
+    }
+    population
+}
+
+/// Loads every chromosome that can be read from the seed sources, in an
+/// order shuffled by the random seed, so that islands which don't have
+/// room for them all take different ones.
+fn load_chromosomes<A: DeserializeOwned>(config: &Config) -> Vec<Vec<A>> {
+    let mut chromosomes = Vec::new();
+    for source in config.seeding.sources.iter() {
+        let values = match read_source(source) {
+            Ok(values) => values,
+            Err(e) => {
+                log::error!("Failed to read seeds from {:?}: {:?}", source, e);
+                continue;
+            }
+        };
+        let found = values.len();
+        let before = chromosomes.len();
+        chromosomes.extend(
+            values
+                .into_iter()
+                .filter_map(|value| serde_json::from_value::<Vec<A>>(value).ok())
+                .filter(|chromosome| !chromosome.is_empty()),
+        );
+        log::info!(
+            "Loaded {} of {} seed chromosomes from {:?}",
+            chromosomes.len() - before,
+            found,
+            source
+        );
+    }
+    chromosomes.shuffle(&mut hash_seed_rng(&config.random_seed));
+    chromosomes
+}
+
+fn read_source(source: &SeedSource) -> Result<Vec<Value>, Error> {
+    match source {
+        SeedSource::Population(path) => {
+            let population: Vec<Value> = undump(path)?;
+            Ok(population.iter().filter_map(chromosome_of).collect())
+        }
+        SeedSource::Champions(dir) => {
+            let mut paths = fs::read_dir(dir)?
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| is_dumped_champion(path))
+                .collect::<Vec<PathBuf>>();
+            paths.sort();
+            let mut chromosomes = Vec::new();
+            for path in paths.iter() {
+                let champion: Value = undump(path)?;
+                chromosomes.extend(chromosome_of(&champion));
+            }
+            Ok(chromosomes)
+        }
+        SeedSource::Chains(path) => {
+            let chains = parse_chains(&fs::read_to_string(path)?)?;
+            Ok(chains.into_iter().map(Value::from).collect())
+        }
+    }
+}
+
+fn is_dumped_champion(path: &Path) -> bool {
+    let is_symlink = fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(true);
+    !is_symlink && path.to_string_lossy().ends_with(".json.gz")
+}
+
+fn chromosome_of(creature: &Value) -> Option<Value> {
+    let chromosome = creature.get("chromosome")?;
+    Some(chromosome.get("chromosome").unwrap_or(chromosome).clone())
+}
+
+fn parse_word(word: &str) -> Result<u64, Error> {
+    if let Some(hex) = word.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).map_err(Error::from)
+    } else {
+        word.parse::<u64>().map_err(Error::from)
+    }
+}
+
+/// Parses a file of hand-written chains, one word per line, separated by
+/// blank lines.
+fn parse_chains(text: &str) -> Result<Vec<Vec<u64>>, Error> {
+    let mut chains = vec![vec![]];
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            if chains.last().map_or(false, |c| !c.is_empty()) {
+                chains.push(vec![]);
+            }
+            continue;
+        }
+        let word = line.split('#').next().unwrap_or("").trim();
+        if !word.is_empty() {
+            chains.last_mut().unwrap().push(parse_word(word)?);
+        }
+    }
+    chains.retain(|c| !c.is_empty());
+    Ok(chains)
+}
+
+#[cfg(test)]
+mod test {
+    use std::hash::Hash;
+
+    use serde::Serialize;
+
+    use crate::util::dump::dump;
+    use crate::util::random::Prng;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_chains() {
+        let text = "# pop rdi\n0x401234\n# then\n0x20\n\n\n0x10 # a constant\n16\n";
+        let chains = parse_chains(text).expect("Failed to parse chains");
+        assert_eq!(chains, vec![vec![0x401234, 0x20], vec![0x10, 16]]);
+        assert!(parse_chains("0xnothex").is_err());
+    }
+
+    #[test]
+    fn test_chromosome_of() {
+        let linear = serde_json::json!({"chromosome": {"chromosome": [1, 2, 3]}, "tag": 0});
+        assert_eq!(chromosome_of(&linear), Some(serde_json::json!([1, 2, 3])));
+        let plain = serde_json::json!({"chromosome": [4, 5]});
+        assert_eq!(chromosome_of(&plain), Some(serde_json::json!([4, 5])));
+        assert_eq!(chromosome_of(&serde_json::json!({"tag": 0})), None);
+    }
+
+    #[derive(Clone, Hash, Serialize)]
+    struct Creature {
+        chromosome: Vec<u64>,
+    }
+
+    impl Genome for Creature {
+        type Allele = u64;
+
+        fn chromosome(&self) -> &[u64] {
+            &self.chromosome
+        }
+
+        fn chromosome_mut(&mut self) -> &mut [u64] {
+            &mut self.chromosome
+        }
+
+        fn random<H: Hash>(_config: &Config, _salt: H) -> Self {
+            Self {
+                chromosome: vec![0xdead],
+            }
+        }
+
+        fn from_chromosome<H: Hash>(
+            chromosome: Vec<u64>,
+            _config: &Config,
+            _salt: H,
+        ) -> Option<Self> {
+            Some(Self { chromosome })
+        }
+
+        fn crossover(parents: &[&Self], _config: &Config, _rng: &mut Prng) -> Self {
+            parents[0].clone()
+        }
+
+        fn mutate(&mut self, _config: &Config, _rng: &mut Prng) {}
+
+        fn incr_num_offspring(&mut self, _n: usize) {}
+    }
+
+    #[test]
+    fn test_seed_from_dumped_population() {
+        let mut config = Config::default();
+        let seeds = [vec![0x1000_u64, 0x2000], vec![0x3000]];
+        let dumped = seeds
+            .iter()
+            .map(|chromosome| Creature {
+                chromosome: chromosome.clone(),
+            })
+            .collect::<Vec<Creature>>();
+        let path = std::env::temp_dir().join(format!(
+            "berbalang_seed_population_{}.json.gz",
+            std::process::id()
+        ));
+        dump(&dumped, &path).expect("Failed to dump population");
+        config.seeding.sources = vec![SeedSource::Population(path.to_string_lossy().to_string())];
+        config.seeding.proportion = 0.5;
+
+        let population = initial_population(&config, 8, |i| Creature::random(&config, i));
+        fs::remove_file(&path).expect("Failed to remove dumped population");
+
+        assert_eq!(population.len(), 8);
+        let count = |chromosome: &[u64]| {
+            population
+                .iter()
+                .filter(|c| c.chromosome() == chromosome)
+                .count()
+        };
+        assert_eq!(count(&seeds[0]), 2);
+        assert_eq!(count(&seeds[1]), 2);
+        assert_eq!(count(&[0xdead]), 4);
+    }
+}
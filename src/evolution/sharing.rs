@@ -0,0 +1,87 @@
+//! Fitness sharing, after Goldberg and Richardson, "Genetic Algorithms with
+//! Sharing for Multimodal Function Optimization" (1987). Creatures that
+//! crowd into the same niche have to split its rewards, which keeps the
+//! population from collapsing onto a single solution.
+use std::hash::Hash;
+
+use hashbrown::HashSet;
+
+use crate::configure::{Distance, SharingConfig};
+use crate::evolution::{Genome, Phenome};
+
+/// The Jaccard distance between the sets of alleles in two chromosomes.
+pub fn chromosome_distance<A: Hash + Eq>(a: &[A], b: &[A]) -> f64 {
+    let a = a.iter().collect::<HashSet<&A>>();
+    let b = b.iter().collect::<HashSet<&A>>();
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    let shared = a.intersection(&b).count();
+    1.0 - shared as f64 / union as f64
+}
+
+pub fn distance<P: Phenome + Genome>(a: &P, b: &P, measure: Distance) -> f64 {
+    match measure {
+        Distance::Chromosome => chromosome_distance(a.chromosome(), b.chromosome()),
+        Distance::Gadgets | Distance::Registers => a.behavioural_distance(b, measure),
+    }
+}
+
+/// The sharing function: 1.0 for identical creatures, falling off to 0.0
+/// at the sharing radius.
+fn share(distance: f64, config: &SharingConfig) -> f64 {
+    if distance >= config.radius {
+        0.0
+    } else {
+        1.0 - (distance / config.radius).powf(config.alpha)
+    }
+}
+
+/// The niche count of each creature: the sum of the sharing function over
+/// its distances to all of the creatures and peers, itself included. This
+/// is never less than 1.0.
+pub fn niche_counts<P: Phenome + Genome>(
+    creatures: &[P],
+    peers: &[&P],
+    config: &SharingConfig,
+) -> Vec<f64> {
+    creatures
+        .iter()
+        .map(|a| {
+            creatures
+                .iter()
+                .chain(peers.iter().copied())
+                .map(|b| share(distance(a, b, config.distance), config))
+                .sum()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configure::SharingScope;
+
+    #[test]
+    fn test_chromosome_distance() {
+        assert!(chromosome_distance(&[1, 2, 3], &[3, 2, 1]).abs() < std::f64::EPSILON);
+        assert!((chromosome_distance(&[1, 2, 3], &[2, 3, 4]) - 0.5).abs() < std::f64::EPSILON);
+        assert!((chromosome_distance(&[1, 2], &[3, 4]) - 1.0).abs() < std::f64::EPSILON);
+        assert!(chromosome_distance::<u64>(&[], &[]).abs() < std::f64::EPSILON);
+    }
+
+    #[test]
+    fn test_share() {
+        let config = SharingConfig {
+            distance: Distance::Chromosome,
+            radius: 0.5,
+            alpha: 1.0,
+            scope: SharingScope::Tournament,
+        };
+        assert!((share(0.0, &config) - 1.0).abs() < std::f64::EPSILON);
+        assert!((share(0.25, &config) - 0.5).abs() < std::f64::EPSILON);
+        assert!(share(0.5, &config).abs() < std::f64::EPSILON);
+        assert!(share(0.9, &config).abs() < std::f64::EPSILON);
+    }
+}
@@ -1,17 +1,21 @@
-use std::cmp::{Ordering, PartialOrd};
-use std::iter;
+use std::collections::VecDeque;
+use std::sync::atomic;
 use std::sync::Arc;
 
-use rand::Rng;
-use rayon::prelude::*;
+use hashbrown::HashMap;
+use non_dominated_sort::non_dominated_sort;
 
-use crate::configure::Config;
+use crate::configure::{
+    AdaptiveRadiusConfig, Config, DiversityMeasure, SharingConfig, SharingScope,
+};
 use crate::evolution::population::pier::Pier;
 use crate::evolution::population::trivial_geography::TrivialGeography;
-use crate::evolution::{Genome, Phenome};
+use crate::evolution::seed::initial_population;
+use crate::evolution::sharing;
+use crate::evolution::{FitnessDominanceOrd, Genome, Phenome, World};
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
-use crate::util::random::hash_seed_rng;
+use crate::util::random::{hash_seed, hash_seed_rng};
 
 pub struct Tournament<E: Develop<P>, P: Phenome + 'static> {
     pub population: TrivialGeography<P>,
@@ -20,6 +24,9 @@ pub struct Tournament<E: Develop<P>, P: Phenome + 'static> {
     pub observer: Observer<P>,
     pub evaluator: E,
     pub pier: Arc<Pier<P>>,
+    /// The features of the most recent combatants, by which the adaptive
+    /// radius measures diversity.
+    pub recent: VecDeque<Vec<u64>>,
 }
 
 impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
@@ -28,13 +35,31 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
         Self: Sized,
     {
         let config = config.clone();
+        if let Some(ref permadeath) = config.tournament.permadeath {
+            assert!(permadeath.every > 0, "permadeath.every must be > 0");
+            assert!(
+                permadeath.min_pop_size > config.tournament.tournament_size,
+                "permadeath.min_pop_size must be larger than a tournament"
+            );
+        }
+        if let Some(ref adaptive) = config.tournament.adaptive_radius {
+            assert!(adaptive.window > 0, "adaptive_radius.window must be > 0");
+            assert!(
+                adaptive.min_radius > config.tournament.tournament_size,
+                "adaptive_radius.min_radius must be larger than a tournament"
+            );
+            assert!(
+                adaptive.min_radius <= adaptive.max_radius,
+                "adaptive_radius.min_radius must not exceed max_radius"
+            );
+        }
         log::debug!("Initializing population");
-        let mut population: TrivialGeography<P> = (0..config.pop_size)
-            .into_par_iter()
-            .map(|i| {
+        let mut population: TrivialGeography<P> =
+            initial_population(&config, config.pop_size, |i| {
                 log::debug!("creating phenome {}/{}", i, config.pop_size);
                 P::random(&config, i)
             })
+            .into_iter()
             .collect();
         population.set_radius(config.tournament.geographic_radius);
         log::debug!("population initialized");
@@ -46,6 +71,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             observer,
             evaluator,
             pier,
+            recent: VecDeque::new(),
         }
     }
 
@@ -58,6 +84,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             config,
             iteration,
             pier,
+            mut recent,
         } = self;
         log::debug!(
             "population size in island {}: {}",
@@ -67,30 +94,68 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
 
         let mut rng = hash_seed_rng(&population);
 
-        let combatants: Vec<P> =
-            population.choose_combatants(config.tournament.tournament_size, &mut rng);
+        let (combatants, range): (Vec<P>, Vec<usize>) =
+            population.choose_combatants_and_range(config.tournament.tournament_size, &mut rng);
 
-        let mut combatants = evaluator
-            .development_pipeline(combatants.into_iter())
+        let (stale, current): (Vec<P>, Vec<P>) = combatants
+            .into_iter()
+            .partition(|p| p.is_fitness_stale(&config));
+        let combatants = evaluator
+            .evaluate(stale, &config)
             .into_iter()
-            .map(|p| evaluator.apply_fitness_function(p))
+            .chain(current.into_iter())
             .map(|e| {
                 observer.observe(e.clone());
                 e
             })
             .collect::<Vec<P>>();
 
-        combatants.sort_by(|a, b| {
-            a.fitness()
-                .partial_cmp(&b.fitness())
-                .unwrap_or(Ordering::Equal)
-        });
+        if let Some(ref adaptive) = config.tournament.adaptive_radius {
+            for combatant in combatants.iter() {
+                recent.push_back(diversity_features(combatant, adaptive.measure));
+            }
+            while recent.len() > adaptive.window {
+                let _ = recent.pop_front();
+            }
+            if iteration % adaptive.window == 0 && recent.len() == adaptive.window {
+                Self::adapt_radius(&mut population, &recent, adaptive, &config);
+            }
+        }
+
+        let mut combatants = match config.tournament.fitness_sharing {
+            None => rank_by_front(combatants),
+            Some(ref sharing) => {
+                let peers = match sharing.scope {
+                    SharingScope::Tournament => vec![],
+                    SharingScope::Deme => range
+                        .iter()
+                        .filter_map(|i| population.get(*i))
+                        .collect::<Vec<&P>>(),
+                };
+                Self::rank_by_shared_fitness(combatants, &peers, sharing)
+            }
+        };
 
         // kill one off for every offspring to be produced
         for _ in 0..config.tournament.num_offspring {
             let _ = combatants.pop();
         }
 
+        // Under permadeath, some or all of the dead go unreplaced, so long
+        // as the island stays above its minimum population.
+        let num_offspring = match config.tournament.permadeath {
+            Some(ref permadeath) if iteration % permadeath.every == 0 => {
+                // the dead have already been popped, so they're added back
+                // in to find the size the island would otherwise return to
+                let remaining = population.len() + combatants.len();
+                let unreplaced = (remaining + config.tournament.num_offspring)
+                    .saturating_sub(permadeath.min_pop_size)
+                    .min(config.tournament.num_offspring);
+                config.tournament.num_offspring - unreplaced
+            }
+            _ => config.tournament.num_offspring,
+        };
+
         let mut survivors = combatants;
 
         // A generation should be considered to have elapsed once
@@ -110,27 +175,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
         // NOTE: migration relies on tournaments being at least 1 larger than
         // the number of parents plus the number of children
         if survivors.len() > config.tournament.num_parents {
-            let mut migrated = false;
-            if rng.gen_range(0.0, 1.0) < config.tournament.migration_rate {
-                log::debug!("Attempting migration...");
-                let emigrant = survivors.pop().unwrap();
-                if let Err(emigrant) = pier.embark(emigrant) {
-                    log::debug!("Pier full, returning emigrant to population");
-                    survivors.push(emigrant);
-                } else {
-                    migrated = true;
-                }
-            }
-            if !migrated {
-                if let Some(immigrant) = pier.disembark() {
-                    log::debug!(
-                        "{} has arrived from the pier of island {}",
-                        immigrant.name(),
-                        config.island_identifier
-                    );
-                    survivors.push(immigrant);
-                }
-            }
+            pier.exchange(&mut survivors, &config, &mut rng);
         }
 
         debug_assert!(survivors.len() >= config.tournament.num_parents);
@@ -139,14 +184,13 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             .iter_mut()
             .take(config.tournament.num_parents)
             .map(|p| {
-                p.incr_num_offspring(config.tournament.num_offspring);
+                p.incr_num_offspring(num_offspring);
                 &*p
             })
             .collect::<Vec<&P>>();
 
-        let offspring: Vec<P> = iter::repeat(())
-            .take(config.tournament.num_offspring)
-            .map(|()| Genome::mate(&parents, &config))
+        let offspring: Vec<P> = (0..num_offspring)
+            .map(|i| Genome::mate(&parents, &config, (iteration, i)))
             .collect::<Vec<_>>();
 
         // return everyone to the population
@@ -156,6 +200,25 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
         for child in offspring.into_iter() {
             population.insert(child).unwrap()
         }
+        if num_offspring < config.tournament.num_offspring {
+            population.compact();
+        }
+        observer
+            .world
+            .population_size
+            .store(population.len(), atomic::Ordering::Relaxed);
+        observer
+            .world
+            .radius
+            .store(population.radius(), atomic::Ordering::Relaxed);
+        if iteration % (config.pop_size / config.tournament.num_offspring) == 0 {
+            log::info!(
+                "Island #{} population size at epoch {}: {}",
+                config.island_identifier,
+                crate::get_epoch_counter(),
+                population.len()
+            );
+        }
 
         Self {
             population,
@@ -164,10 +227,167 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             observer,
             evaluator,
             pier,
+            recent,
+        }
+    }
+
+    /// Narrows the geographic radius when the recent combatants' diversity
+    /// has collapsed, and widens it when diversity is healthy.
+    fn adapt_radius(
+        population: &mut TrivialGeography<P>,
+        recent: &VecDeque<Vec<u64>>,
+        adaptive: &AdaptiveRadiusConfig,
+        config: &Config,
+    ) {
+        let diversity = match diversity(recent.iter(), adaptive.measure) {
+            Some(diversity) => diversity,
+            None => return,
+        };
+        let radius = population.radius();
+        let new_radius = if diversity < adaptive.low {
+            radius
+                .saturating_sub(adaptive.step)
+                .max(adaptive.min_radius)
+        } else if diversity > adaptive.high {
+            (radius + adaptive.step).min(adaptive.max_radius)
+        } else {
+            radius
+        };
+        log::debug!(
+            "Island #{} {:?} diversity: {}",
+            config.island_identifier,
+            adaptive.measure,
+            diversity
+        );
+        if new_radius != radius {
+            log::info!(
+                "Island #{} {:?} diversity is {}; adjusting radius from {} to {}",
+                config.island_identifier,
+                adaptive.measure,
+                diversity,
+                radius,
+                new_radius
+            );
+            population.set_radius(new_radius);
         }
     }
 
+    /// Sorts the combatants by their front rank, counting from 1, multiplied
+    /// by their niche count, so that a creature in a crowded niche can lose
+    /// to one on a worse front that has found somewhere of its own. Working
+    /// with ranks rather than raw scores lets this cope with any fitness
+    /// representation.
+    fn rank_by_shared_fitness(combatants: Vec<P>, peers: &[&P], config: &SharingConfig) -> Vec<P> {
+        let ranks = front_ranks(&combatants);
+        let niche_counts = sharing::niche_counts(&combatants, peers, config);
+        let mut ranked = combatants
+            .into_iter()
+            .zip(
+                ranks
+                    .into_iter()
+                    .zip(niche_counts)
+                    .map(|(rank, niche_count)| (rank + 1) as f64 * niche_count),
+            )
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        ranked.into_iter().map(|(p, _)| p).collect()
+    }
+
     fn island_epoch(iteration: usize, config: &Config) -> usize {
         iteration / (config.pop_size / config.tournament.num_offspring)
     }
 }
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> World for Tournament<E, P> {
+    fn evolve(self) -> Self {
+        Tournament::evolve(self)
+    }
+}
+
+/// Sorts the combatants by the rank of the non-dominated front they
+/// fall on. When fitness is totally ordered, as it is for weighted
+/// scalars, this is the same as sorting them by fitness, but it also
+/// copes with Pareto fitness, where some pairs are incomparable.
+pub fn rank_by_front<P: Phenome>(combatants: Vec<P>) -> Vec<P> {
+    let ranks = front_ranks(&combatants);
+    let mut ranked = combatants.into_iter().zip(ranks).collect::<Vec<_>>();
+    ranked.sort_by_key(|(_, rank)| *rank);
+    ranked.into_iter().map(|(p, _)| p).collect()
+}
+
+fn front_ranks<P: Phenome>(combatants: &[P]) -> Vec<usize> {
+    let mut ranks = vec![0; combatants.len()];
+    let mut front = non_dominated_sort(combatants, &FitnessDominanceOrd);
+    while !front.is_empty() {
+        for i in front.current_front_indices() {
+            ranks[*i] = front.rank();
+        }
+        front = front.next_front();
+    }
+    ranks
+}
+
+/// The features of a creature that count towards the diversity measure:
+/// hashes of its alleles, or the addresses of the gadgets it executed.
+fn diversity_features<P: Phenome + Genome>(creature: &P, measure: DiversityMeasure) -> Vec<u64> {
+    match measure {
+        DiversityMeasure::GenotypeEntropy => creature
+            .chromosome()
+            .iter()
+            .map(|allele| u64::from_le_bytes(hash_seed(allele)))
+            .collect(),
+        DiversityMeasure::UniqueGadgets => creature
+            .gadgets_executed()
+            .map(|gadgets| gadgets.iter().cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// The diversity of a collection of features, between 0.0 and 1.0. For
+/// genotype entropy, this is the Shannon entropy of the allele hashes,
+/// divided by the largest possible for that many; for gadgets, it's the
+/// fraction that are distinct. `None` if there are too few features to say.
+fn diversity<'a, I: Iterator<Item = &'a Vec<u64>>>(
+    features: I,
+    measure: DiversityMeasure,
+) -> Option<f64> {
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for feature in features.flatten() {
+        *counts.entry(*feature).or_insert(0) += 1;
+    }
+    let total = counts.values().sum::<usize>();
+    if total <= 1 {
+        return None;
+    }
+    let total = total as f64;
+    if let DiversityMeasure::UniqueGadgets = measure {
+        return Some(counts.len() as f64 / total);
+    }
+    let entropy = counts
+        .values()
+        .map(|&c| c as f64 / total)
+        .map(|p| -(p * p.log2()))
+        .sum::<f64>();
+    Some(entropy / total.log2())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diversity() {
+        let entropy = DiversityMeasure::GenotypeEntropy;
+        let gadgets = DiversityMeasure::UniqueGadgets;
+        let same = vec![vec![1, 1], vec![1, 1]];
+        assert!(diversity(same.iter(), entropy).unwrap().abs() < std::f64::EPSILON);
+        assert!((diversity(same.iter(), gadgets).unwrap() - 0.25).abs() < std::f64::EPSILON);
+        let distinct = vec![vec![1, 2], vec![3, 4]];
+        assert!((diversity(distinct.iter(), entropy).unwrap() - 1.0).abs() < std::f64::EPSILON);
+        assert!((diversity(distinct.iter(), gadgets).unwrap() - 1.0).abs() < std::f64::EPSILON);
+        let half = vec![vec![1, 1], vec![2, 2]];
+        assert!((diversity(half.iter(), entropy).unwrap() - 0.5).abs() < std::f64::EPSILON);
+        assert!((diversity(half.iter(), gadgets).unwrap() - 0.5).abs() < std::f64::EPSILON);
+        assert!(diversity(vec![vec![7]].iter(), entropy).is_none());
+    }
+}
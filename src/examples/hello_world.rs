@@ -15,7 +15,7 @@ use crate::evolution::{Genome, Phenome};
 use crate::observer::Window;
 use crate::util::count_min_sketch::CountMinSketch;
 use crate::util::levy_flight::levy_decision;
-use crate::util::random::hash_seed_rng;
+use crate::util::random::{hash_seed_rng, Prng};
 use crate::{evolution::tournament::*, observer::Observer, ontogenesis::Develop};
 
 pub type Fitness = Vec<f64>;
@@ -135,8 +135,7 @@ impl Genome for Genotype {
         }
     }
 
-    fn crossover(mates: &[&Self], _config: &Config) -> Self {
-        let mut rng = hash_seed_rng(&mates[0]);
+    fn crossover(mates: &[&Self], _config: &Config, rng: &mut Prng) -> Self {
         let father = &mates[0];
         let mother = &mates[1];
         let split_m: usize = rng.gen::<usize>() % mother.len();
@@ -153,11 +152,10 @@ impl Genome for Genotype {
         }
     }
 
-    fn mutate(&mut self, config: &Config) {
-        let mut rng = hash_seed_rng(&self);
+    fn mutate(&mut self, config: &Config, rng: &mut Prng) {
         let mutation = rng.gen::<u8>() % 4;
         for i in 0..self.len() {
-            if !levy_decision(&mut rng, self.len(), config.mutation_exponent) {
+            if !levy_decision(rng, self.len(), config.mutation_exponent) {
                 continue;
             }
             match mutation {
@@ -258,7 +256,7 @@ pub fn run(config: Config) {
     let fitness_fn = Box::new(fitness_function);
     let observer = Observer::spawn(&config, report_fn);
     let evaluator = evaluation::Evaluator::spawn(&config, fitness_fn);
-    let pier = Pier::new(4); // FIXME: don't hardcode, make this the number of islands, say
+    let pier = Pier::new(&config);
     let mut world = Tournament::<evaluation::Evaluator, Genotype>::new(
         &config,
         observer,
@@ -8,6 +8,7 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::configure::{Config, IOProblem, Selection};
+use crate::evolution::alps::Alps;
 use crate::evolution::metropolis::Metropolis;
 use crate::evolution::pareto_roulette::Roulette;
 use crate::evolution::population::pier::Pier;
@@ -18,7 +19,7 @@ use crate::ontogenesis::FitnessFn;
 use crate::util;
 use crate::util::count_min_sketch::CountMinSketch;
 use crate::util::levy_flight::levy_decision;
-use crate::util::random::{hash_seed, hash_seed_rng};
+use crate::util::random::{hash_seed, hash_seed_rng, Prng};
 
 pub type Fitness<'a> = Weighted<'a>;
 // try setting fitness to (usize, usize);
@@ -118,12 +119,12 @@ pub mod machine {
     }
 
     impl Inst {
-        pub fn random(config: &LinearGpConfig) -> Self {
+        pub fn random<R: Rng>(config: &LinearGpConfig, rng: &mut R) -> Self {
             let num_registers = config.num_registers.unwrap();
             Self {
-                op: rand::random::<Op>(),
-                a: rand::random::<usize>() % num_registers,
-                b: rand::random::<usize>() % num_registers,
+                op: rng.gen::<Op>(),
+                a: rng.gen::<usize>() % num_registers,
+                b: rng.gen::<usize>() % num_registers,
             }
         }
 
@@ -133,7 +134,7 @@ pub mod machine {
             let mutation = rng.gen::<u8>() % 4;
 
             match mutation {
-                0 => self.op = rand::random(),
+                0 => self.op = rng.gen(),
                 1 => self.a = (self.a + 1) % num_registers,
                 2 => self.b = (self.b + 1) % num_registers,
                 3 => std::mem::swap(&mut self.a, &mut self.b),
@@ -265,7 +266,7 @@ fn random_chromosome<H: Hash>(config: &Config, seed: H) -> Genotype {
     let mut rng = hash_seed_rng(&seed);
     let len = rng.gen_range(config.min_init_len, config.max_init_len) + 1;
     iter::repeat(())
-        .map(|()| machine::Inst::random(&config.linear_gp))
+        .map(|()| machine::Inst::random(&config.linear_gp, &mut rng))
         .take(len)
         .collect()
 }
@@ -396,6 +397,10 @@ impl Genome for Creature {
         self.num_offspring += n
     }
 
+    fn generation(&self) -> usize {
+        self.generation
+    }
+
     fn chromosome(&self) -> &[Self::Allele] {
         &self.chromosome
     }
@@ -422,11 +427,10 @@ impl Genome for Creature {
         }
     }
 
-    fn crossover(mates: &[&Self], config: &Config) -> Self {
+    fn crossover(mates: &[&Self], config: &Config, rng: &mut Prng) -> Self {
         let distribution = rand_distr::Exp::new(config.crossover_period)
             .expect("Failed to create random distribution");
         let parental_chromosomes = mates.iter().map(|m| m.chromosome()).collect::<Vec<_>>();
-        let mut rng = hash_seed_rng(&mates[0]);
         let (chromosome, chromosome_parentage, parent_names) =
             // Check to see if we're performing a crossover or just cloning
             if rng.gen_range(0.0, 1.0) < config.crossover_rate {
@@ -449,7 +453,7 @@ impl Genome for Creature {
             chromosome_mutation: vec![None; length],
             answers: None,
             fitness: None,
-            tag: rand::random::<u64>(),
+            tag: rng.gen::<u64>(),
             //crossover_mask: 0,
             name,
             parents: parent_names,
@@ -458,12 +462,11 @@ impl Genome for Creature {
         }
     }
 
-    fn mutate(&mut self, config: &Config) {
-        let mut rng = hash_seed_rng(&self);
+    fn mutate(&mut self, config: &Config, rng: &mut Prng) {
         //let i = rng.gen_range(0, self.len());
         //self.crossover_mask ^= 1 << rng.gen_range(0, 64);
         for i in 0..self.len() {
-            if !levy_decision(&mut rng, self.len(), config.mutation_exponent) {
+            if !levy_decision(rng, self.len(), config.mutation_exponent) {
                 continue;
             }
             let seed = hash_seed(&rng.gen::<u64>());
@@ -648,7 +651,7 @@ mod evaluation {
                 }
             })
             .fold(0, |a, b| a + b);
-        let mut fitness = Weighted::new(&config.fitness.weighting);
+        let mut fitness = Weighted::from_config(&config.fitness);
         fitness.insert("error_rate", score as f64);
         // TODO: refactor types
         //creature.set_fitness((fitness, 0.0, 0.0, len));
@@ -732,7 +735,7 @@ pub fn run(config: Config) {
 
     match selection {
         Selection::Tournament => {
-            let pier = Pier::new(4); // FIXME: don't hardcode
+            let pier = Pier::new(&config);
             let mut world = Tournament::<evaluation::Evaluator, Creature>::new(
                 &config,
                 observer,
@@ -743,6 +746,18 @@ pub fn run(config: Config) {
                 world = world.evolve();
             }
         }
+        Selection::Alps => {
+            let pier = Pier::new(&config);
+            let mut world = Alps::<evaluation::Evaluator, Creature>::new(
+                &config,
+                observer,
+                evaluator,
+                Arc::new(pier),
+            );
+            while crate::keep_going() {
+                world = world.evolve();
+            }
+        }
         Selection::Roulette => {
             let mut world = Roulette::<evaluation::Evaluator, Creature, CreatureDominanceOrd>::new(
                 &config,
@@ -755,8 +770,13 @@ pub fn run(config: Config) {
             }
         }
         Selection::Metropolis => {
+            let evaluators = std::iter::once(evaluator)
+                .chain((1..config.metropolis.replicas.max(1)).map(|_| {
+                    evaluation::Evaluator::spawn(&config, Box::new(evaluation::fitness_function))
+                }))
+                .collect::<Vec<_>>();
             let mut world =
-                Metropolis::<evaluation::Evaluator, Creature>::new(&config, observer, evaluator);
+                Metropolis::<evaluation::Evaluator, Creature>::new(&config, observer, evaluators);
             while crate::keep_going() {
                 world = world.evolve();
             }
@@ -764,3 +784,117 @@ pub fn run(config: Config) {
         sel => unimplemented!("{:?} not implemented for {:?}", sel, config.job),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::configure::PermadeathConfig;
+
+    use super::*;
+
+    fn reproducible_config() -> Config {
+        let data_directory = std::env::temp_dir().join("berbalang_reproducibility");
+        let toml = format!(
+            r#"
+            job = "LinearGp"
+            selection = "Tournament"
+            random_seed = 0xdeadbeef
+            reproducible = true
+            num_islands = 1
+            mutation_exponent = 2.0
+            crossover_period = 2.0
+            crossover_rate = 0.9
+            max_init_len = 20
+            min_init_len = 5
+            max_length = 100
+            pop_size = 64
+            num_epochs = 0
+
+            [fitness]
+            function = "error_rate"
+            weighting = "error_rate"
+            priority = "error_rate"
+            target = -1.0
+            eval_by_case = false
+            dynamic = false
+
+            [tournament]
+            tournament_size = 6
+            geographic_radius = 8
+            migration_rate = 0.0
+            num_offspring = 2
+            num_parents = 2
+
+            [linear_gp]
+            max_steps = 0x100
+
+            [observer]
+            dump_population = false
+            dump_soup = false
+            data_directory = {:?}
+            population_name = "reproducibility"
+            window_size = 100
+            report_every = 100000
+            dump_every = 100000
+
+            [data]
+            path = {:?}
+            "#,
+            data_directory.to_string_lossy(),
+            concat!(env!("CARGO_MANIFEST_DIR"), "/data/square.tsv"),
+        );
+        let mut config: Config = toml::from_str(&toml).expect("Failed to parse config");
+        config.set_data_directory();
+        config
+    }
+
+    /// Runs a few tournaments, and returns the world afterwards.
+    fn tournament(config: &Config, steps: usize) -> Tournament<evaluation::Evaluator, Creature> {
+        let (config, observer, evaluator) = prepare(config.clone());
+        let pier = Arc::new(Pier::new(&config));
+        let mut world =
+            Tournament::<evaluation::Evaluator, Creature>::new(&config, observer, evaluator, pier);
+        for _ in 0..steps {
+            world = world.evolve();
+        }
+        world
+    }
+
+    /// Runs a few tournaments, and returns what can be seen of the
+    /// population afterwards.
+    fn run_tournaments(config: &Config, steps: usize) -> Vec<String> {
+        tournament(config, steps)
+            .population
+            .into_iter()
+            .map(|creature| {
+                serde_json::to_string(&(
+                    creature.tag,
+                    &creature.chromosome,
+                    creature.fitness.as_ref().map(|f| &f.scores),
+                ))
+                .expect("Failed to serialize creature")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_seed_same_population() {
+        let config = reproducible_config();
+        let first = run_tournaments(&config, 50);
+        let second = run_tournaments(&config, 50);
+        assert_eq!(first.len(), config.pop_size);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_permadeath_stops_at_min_pop_size() {
+        let mut config = reproducible_config();
+        config.tournament.permadeath = Some(PermadeathConfig {
+            every: 1,
+            min_pop_size: 40,
+        });
+        // two of the dead go unreplaced each time, so 12 tournaments take
+        // the island from 64 down to 40, and the rest must hold it there
+        let world = tournament(&config, 30);
+        assert_eq!(world.population.len(), 40);
+    }
+}
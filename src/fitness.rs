@@ -4,14 +4,76 @@ use std::fmt;
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
 use std::ops::Index;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, RwLock};
 
+use fasteval::{Compiler, Evaler, Instruction, Parser, Slab};
+use hashbrown::HashMap;
 use itertools::Itertools;
 use serde::export::Formatter;
 use serde::{Deserialize, Serialize};
 
+use crate::configure::FitnessConfig;
+use crate::error::Error;
+
 pub type FitnessMap<'a> = BTreeMap<&'a str, f64>;
 
+/// Fitness scores that are recorded as named components, like those of
+/// `Weighted`, can be compared with one another in several ways. Lower
+/// scores are always taken to be better.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Representation {
+    /// Compare the scalar values of the weighting expression.
+    Weighted,
+    /// Compare the components by Pareto dominance.
+    Pareto,
+    /// Compare the named components one at a time, in the order given,
+    /// moving on to the next only to break ties.
+    Lexicographic(Vec<String>),
+}
+
+impl Default for Representation {
+    fn default() -> Self {
+        Representation::Weighted
+    }
+}
+
+/// One map dominates another if it's no worse in every component, and
+/// better in at least one. Maps with different components can't be compared.
+fn pareto_cmp(a: &FitnessMap<'_>, b: &FitnessMap<'_>) -> Option<Ordering> {
+    if a.len() != b.len() {
+        return None;
+    }
+    let mut less = false;
+    let mut greater = false;
+    for (k, x) in a.iter() {
+        let y = b.get(k)?;
+        if x < y {
+            less = true;
+        } else if x > y {
+            greater = true;
+        }
+    }
+    match (less, greater) {
+        (true, false) => Some(Ordering::Less),
+        (false, true) => Some(Ordering::Greater),
+        (false, false) => Some(Ordering::Equal),
+        (true, true) => None,
+    }
+}
+
+/// Missing components are treated as maximally bad.
+fn lexicographic_cmp(order: &[String], a: &FitnessMap<'_>, b: &FitnessMap<'_>) -> Option<Ordering> {
+    for k in order {
+        let x = a.get(k.as_str()).cloned().unwrap_or(f64::MAX);
+        let y = b.get(k.as_str()).cloned().unwrap_or(f64::MAX);
+        match x.partial_cmp(&y) {
+            Some(Ordering::Equal) => continue,
+            ord => return ord,
+        }
+    }
+    Some(Ordering::Equal)
+}
+
 pub trait HasScalar {
     fn scalar(&self) -> f64;
 }
@@ -25,6 +87,16 @@ impl HasScalar for Vec<f64> {
 pub trait FitnessScore:
     Sized + PartialEq + Debug + Send + Clone + PartialOrd + Serialize + PartialOrd + HasScalar
 {
+    /// The epoch in which the score was measured, if it keeps track.
+    fn epoch(&self) -> Option<usize> {
+        None
+    }
+
+    /// The fitness stage under which the score was measured, if it keeps
+    /// track.
+    fn stage(&self) -> Option<usize> {
+        None
+    }
 }
 
 impl FitnessScore for Vec<f64> {}
@@ -106,13 +178,7 @@ impl PartialOrd for Pareto<'static> {
             other.0.len(),
             "vectors must have the same length in order to perform Pareto comparisons"
         );
-        if self < other {
-            Some(Ordering::Less)
-        } else if other < self {
-            Some(Ordering::Greater)
-        } else {
-            None
-        }
+        pareto_cmp(&self.0, &other.0)
     }
 
     fn lt(&self, other: &Self) -> bool {
@@ -289,16 +355,91 @@ impl From<BTreeMap<&'static str, f64>> for ShuffleFit {
 
 impl FitnessScore for ShuffleFit {}
 
+/// A weighting or priority expression, parsed and compiled once, and
+/// then evaluated against the scores of any number of creatures. Besides
+/// the names of the scores, the expression may refer to the current
+/// epoch, as `E`, and to the stage the scores were measured under, as
+/// `stage`.
+pub struct Expression {
+    slab: Slab,
+    instruction: Instruction,
+}
+
+impl Expression {
+    pub fn compile(expr: &str) -> Result<Self, Error> {
+        let parser = Parser::new();
+        let mut slab = Slab::new();
+        // See the `parse` documentation to understand why we use `from` like this:
+        let instruction = parser
+            .parse(expr, &mut slab.ps)?
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs);
+        Ok(Self { slab, instruction })
+    }
+
+    /// Returns the value of the expression, along with whether or not it
+    /// depended on the epoch (in which case it shouldn't be cached).
+    pub fn eval(&self, scores: &FitnessMap<'_>, stage: usize) -> Result<(f64, bool), Error> {
+        let mut uses_epoch = false;
+        let mut ns = |name: &str, _args: Vec<f64>| -> Option<f64> {
+            match name {
+                "E" => {
+                    uses_epoch = true;
+                    Some(crate::get_epoch_counter() as f64)
+                }
+                "stage" => Some(stage as f64),
+                _ => scores.get(name).cloned(),
+            }
+        };
+        let res = self.instruction.eval(&self.slab, &mut ns)?;
+        Ok((res, uses_epoch))
+    }
+}
+
+/// Compiled expressions, looked up by their source text. A single
+/// `Expressions` is shared by every clone of a run's `FitnessConfig`,
+/// and by every `Weighted` score built from it, so each expression is
+/// compiled only once per run.
+#[derive(Default)]
+pub struct Expressions(RwLock<HashMap<String, Arc<Expression>>>);
+
+impl Expressions {
+    pub fn get(&self, expr: &str) -> Result<Arc<Expression>, Error> {
+        if let Some(compiled) = self.0.read().expect("poisoned").get(expr) {
+            return Ok(compiled.clone());
+        }
+        let compiled = Arc::new(Expression::compile(expr)?);
+        self.0
+            .write()
+            .expect("poisoned")
+            .insert(expr.to_string(), compiled.clone());
+        Ok(compiled)
+    }
+}
+
+impl fmt::Debug for Expressions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.0.read().expect("poisoned").keys())
+            .finish()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Weighted<'a> {
-    // weights: Arc<FitnessMap<String, String>>,
     weighting: String,
-    //   #[serde(skip)]
-    //   pub weights: FitnessMap<String, fasteval::Instruction>,
-    //#[serde(skip)]
-    //slab: Mutex<Slab>,
+    #[serde(skip)]
+    expressions: Arc<Expressions>,
     #[serde(borrow)]
     pub scores: BTreeMap<&'a str, f64>,
+    #[serde(default)]
+    representation: Representation,
+    /// The epoch in which the scores were measured.
+    #[serde(default)]
+    epoch: usize,
+    /// The fitness stage under which the scores were measured.
+    #[serde(default)]
+    stage: usize,
     cached_scalar: Mutex<Option<f64>>,
 }
 
@@ -308,100 +449,79 @@ impl PartialEq for Weighted<'_> {
     }
 }
 
-// fn compile_weight_expression(
-//     expr: &str,
-//     slab: &mut fasteval::Slab,
-//     parser: &fasteval::Parser,
-// ) -> fasteval::Instruction {
-//     // See the `parse` documentation to understand why we use `from` like this:
-//     parser
-//         .parse(expr, &mut slab.ps)
-//         .expect("Failed to parse expression")
-//         .from(&slab.ps)
-//         .compile(&slab.ps, &mut slab.cs)
-// }
-
 impl Clone for Weighted<'_> {
     fn clone(&self) -> Self {
         Self {
             cached_scalar: Mutex::new(None),
             weighting: self.weighting.clone(),
+            expressions: self.expressions.clone(),
             scores: self.scores.clone(),
+            representation: self.representation.clone(),
+            epoch: self.epoch,
+            stage: self.stage,
         }
     }
 }
 
-// TODO: this could be optimized quite a bit by parsing the fasteval expressions
-// // when constructing the Weighted instance.
-// fn apply_weighting(
-//     weighting: &fasteval::Instruction,
-//     score: f64,
-//     mut slab: &mut Slab,
-// ) -> Result<f64, Error> {
-//     let mut map = BTreeMap::new();
-//     map.insert("x".to_string(), score);
-//     let res = fasteval::eval_compiled_ref!(weighting, &mut slab, &mut map);
-//     Ok(res)
-// }
-
-// fn apply_weighting(weighting: &str, score: f64) -> (bool, f64) {
-//     let mut ns = BTreeMap::new();
-//     let mut should_cache = true;
-//     ns.insert("x", score);
-//     if weighting.contains('E') {
-//         should_cache = false;
-//         ns.insert("E", get_epoch_counter() as f64);
-//     }
-//     let score =
-//         fasteval::ez_eval(weighting, &mut ns).expect("Failed to evaluate weighting expression");
-//     (should_cache, score)
-// }
-
-// fn compile_weight_map(
-//     weights: &FitnessMap<String, String>,
-//     mut slab: &mut Slab,
-// ) -> FitnessMap<String, fasteval::Instruction> {
-//     let parser = fasteval::Parser::new();
-//     let mut weight_map = FitnessMap::new();
-//     for (attr, weight) in weights.iter() {
-//         let compiled = compile_weight_expression(weight, &mut slab, &parser);
-//         weight_map.insert(attr.clone(), compiled);
-//     }
-//     weight_map
-// }
-
 impl Weighted<'static> {
     pub fn new(weighting: &str) -> Self {
         Self {
-            //slab: Mutex::new(slab),
             weighting: weighting.to_string(),
-            //weights: weight_map,
+            expressions: Arc::new(Expressions::default()),
             scores: FitnessMap::new(),
+            representation: Representation::Weighted,
+            epoch: crate::get_epoch_counter(),
+            stage: 0,
             cached_scalar: Mutex::new(None),
         }
     }
 
+    /// Uses the current weighting expression and the representation
+    /// chosen in the config, along with its compiled expressions.
+    pub fn from_config(config: &FitnessConfig) -> Self {
+        Self {
+            representation: config.representation.clone(),
+            expressions: config.expressions.clone(),
+            stage: config.stage(),
+            ..Self::new(config.weighting())
+        }
+    }
+
     pub fn insert(&mut self, key: &'static str, val: f64) {
         self.scores.insert(key, val);
+        self.invalidate_cached_scalar();
+    }
+
+    /// The cached scalar is only good for as long as the scores don't change.
+    pub fn invalidate_cached_scalar(&mut self) {
+        *self.cached_scalar.get_mut().expect("poisoned") = None;
     }
 
     pub fn scalar(&self) -> f64 {
         let mut cache = self.cached_scalar.lock().expect("poisoned");
         if let Some(res) = *cache {
             return res;
-        } else {
-            let res = self.scalar_with_expression(&self.weighting);
+        }
+        let (res, uses_epoch) = self.eval(&self.weighting);
+        if !uses_epoch {
             *cache = Some(res);
-            res
         }
+        res
     }
 
     pub fn scalar_with_expression(&self, expr: &str) -> f64 {
+        self.eval(expr).0
+    }
+
+    fn eval(&self, expr: &str) -> (f64, bool) {
         if self.scores.is_empty() {
-            return f64::MAX;
+            return (f64::MAX, false);
         }
-        let mut ns = self.scores.clone();
-        match fasteval::ez_eval(expr, &mut ns) {
+        match self
+            .expressions
+            .get(expr)
+            .and_then(|compiled| compiled.eval(&self.scores, self.stage))
+        {
             Err(e) => panic!(
                 "Failed to evaluate expression {:?} with scores {:?}: {:?}",
                 expr, self.scores, e
@@ -410,6 +530,12 @@ impl Weighted<'static> {
         }
     }
 
+    /// Records the stage under which the scores were measured, in case it
+    /// has moved on since the fitness was created.
+    pub fn set_stage(&mut self, stage: usize) {
+        self.stage = stage;
+    }
+
     pub fn declare_failure(&mut self) {
         *self.cached_scalar.get_mut().unwrap() = Some(f64::MAX)
     }
@@ -423,14 +549,36 @@ impl HasScalar for Weighted<'static> {
 
 impl PartialOrd for Weighted<'static> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.scalar().partial_cmp(&other.scalar())
+        // creatures that have been declared failures have no scores
+        match (self.scores.is_empty(), other.scores.is_empty()) {
+            (true, true) => return Some(Ordering::Equal),
+            (true, false) => return Some(Ordering::Greater),
+            (false, true) => return Some(Ordering::Less),
+            (false, false) => (),
+        }
+        match &self.representation {
+            Representation::Weighted => self.scalar().partial_cmp(&other.scalar()),
+            Representation::Pareto => pareto_cmp(&self.scores, &other.scores),
+            Representation::Lexicographic(order) => {
+                lexicographic_cmp(order, &self.scores, &other.scores)
+            }
+        }
     }
 }
 
-impl FitnessScore for Weighted<'static> {}
+impl FitnessScore for Weighted<'static> {
+    fn epoch(&self) -> Option<usize> {
+        Some(self.epoch)
+    }
+
+    fn stage(&self) -> Option<usize> {
+        Some(self.stage)
+    }
+}
 
 impl MapFit for Weighted<'static> {
     fn inner_mut(&mut self) -> &mut BTreeMap<&'static str, f64> {
+        self.invalidate_cached_scalar();
         &mut self.scores
     }
 
@@ -449,9 +597,11 @@ impl MapFit for Weighted<'static> {
         debug_assert!(!frame.is_empty(), "Don't try to average empty frames");
         let mut map = FitnessMap::new();
         let mut weighting = None;
+        let mut representation = None;
         for p in frame.iter() {
             if weighting.is_none() {
                 weighting = Some(p.weighting.clone());
+                representation = Some(p.representation.clone());
             }
             for (&k, &v) in p.inner().iter() {
                 *(map.entry(k).or_insert(0.0)) += v;
@@ -464,7 +614,12 @@ impl MapFit for Weighted<'static> {
         let weighting = weighting.unwrap();
         Self {
             weighting,
+            expressions: frame[0].expressions.clone(),
             scores: map,
+            // the average is as fresh as its freshest member
+            epoch: frame.iter().map(|p| p.epoch).max().unwrap_or(0),
+            stage: frame.iter().map(|p| p.stage).max().unwrap_or(0),
+            representation: representation.unwrap_or_default(),
             cached_scalar: Mutex::new(None),
         }
     }
@@ -504,6 +659,77 @@ mod test {
         assert_eq!(ps[0], &p2);
     }
 
+    #[test]
+    fn test_weighted_representations() {
+        let mut a = Weighted::new("x + y");
+        a.insert("x", 1.0);
+        a.insert("y", 5.0);
+        let mut b = Weighted::new("x + y");
+        b.insert("x", 2.0);
+        b.insert("y", 3.0);
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Greater));
+
+        a.representation = Representation::Pareto;
+        assert_eq!(a.partial_cmp(&b), None);
+        b.insert("y", 6.0);
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Less));
+
+        a.representation = Representation::Lexicographic(vec!["y".into(), "x".into()]);
+        b.insert("y", 5.0);
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Less));
+        b.insert("x", 1.0);
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn test_compiled_expressions() {
+        let mut a = Weighted::new("2 * x + y");
+        a.insert("x", 3.0);
+        a.insert("y", 1.0);
+        assert!((a.scalar() - 7.0).abs() < std::f64::EPSILON);
+        assert_eq!(*a.cached_scalar.lock().unwrap(), Some(7.0));
+
+        // the clone shares the compiled expression
+        let b = a.clone();
+        assert!(Arc::ptr_eq(
+            &a.expressions.get("2 * x + y").unwrap(),
+            &b.expressions.get("2 * x + y").unwrap()
+        ));
+
+        // scalars that depend on the epoch aren't cached
+        let mut c = Weighted::new("x + 0 * E");
+        c.insert("x", 3.0);
+        assert!((c.scalar() - 3.0).abs() < std::f64::EPSILON);
+        assert_eq!(*c.cached_scalar.lock().unwrap(), None);
+
+        assert!(Expression::compile("x +").is_err());
+    }
+
+    #[test]
+    fn test_average_weighted() {
+        let mut a = Weighted::new("x + y");
+        a.insert("x", 1.0);
+        a.insert("y", 4.0);
+        let mut b = Weighted::new("x + y");
+        b.insert("x", 3.0);
+        b.insert("y", 0.0);
+
+        let avg = Weighted::average(&[&a, &b]);
+        assert!((avg["x"] - 2.0).abs() < std::f64::EPSILON);
+        assert!((avg["y"] - 2.0).abs() < std::f64::EPSILON);
+        assert!((avg.scalar() - 4.0).abs() < std::f64::EPSILON);
+        // the average shares the frame's compiled expressions
+        assert!(Arc::ptr_eq(&avg.expressions, &a.expressions));
+
+        a.epoch = 3;
+        b.epoch = 7;
+        assert_eq!(Weighted::average(&[&a, &b]).epoch, 7);
+
+        a.set_stage(1);
+        assert_eq!(Weighted::average(&[&a, &b]).stage(), Some(1));
+        assert!((a.scalar_with_expression("x + stage") - 2.0).abs() < std::f64::EPSILON);
+    }
+
     // #[test]
     // fn test_find_minima() {
     //     fn random_pareto() -> Pareto<'static> {
@@ -93,6 +93,10 @@ fn main() {
 
     logger::init(&config.observer.population_name);
 
+    if config.reproducible {
+        config.make_reproducible();
+    }
+
     match config.job {
         Job::LinearGp => {
             linear_gp::run(config);
@@ -1,8 +1,10 @@
 // A Logger needs to asynchronously gather and periodically
 // record information on the evolutionary process.
 
+use std::cmp::Ordering;
 use std::fs;
 use std::path::Path;
+use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::{spawn, JoinHandle};
@@ -12,6 +14,7 @@ use serde::Serialize;
 
 use crate::configure::Config;
 use crate::evolution::{Genome, Phenome};
+use crate::fitness::FitnessScore;
 use crate::hashmap;
 use crate::util::count_min_sketch::CountMinSketch;
 use crate::util::dump::dump;
@@ -38,6 +41,15 @@ fn stat_writer(config: &Config, name: &str) -> csv::Writer<fs::File> {
 pub struct Observer<O: Send> {
     pub handle: JoinHandle<()>,
     tx: Sender<O>,
+    pub world: Arc<WorldStats>,
+}
+
+/// Measurements of the world, rather than of any creature in it, which
+/// the world updates and the observation window reports.
+#[derive(Debug)]
+pub struct WorldStats {
+    pub population_size: AtomicUsize,
+    pub radius: AtomicUsize,
 }
 
 pub type ReportFn<T> = Box<dyn Fn(&Window<T>, usize, &Config) -> () + Sync + Send + 'static>;
@@ -58,7 +70,7 @@ pub fn default_report_fn<P: Phenome + Genome>(window: &Window<P>, counter: usize
         / frame.len() as f64;
     let avg_fit: f64 = frame
         .iter()
-        .filter_map(|g| g.scalar_fitness(&window.config.fitness.weighting))
+        .filter_map(|g| g.scalar_fitness(window.config.fitness.weighting()))
         .sum::<f64>()
         / frame.len() as f64;
     log::info!(
@@ -81,14 +93,17 @@ pub struct Window<O: Phenome + 'static> {
     report_fn: ReportFn<O>,
     pub best: Option<O>,
     pub champion: Option<O>,
+    /// The fitness stage the window's contents were scored under.
+    stage: usize,
     // priority fitness best
     pub archive: Vec<O>,
     #[allow(dead_code)] // TODO: re-establish pareto archive as optional
     stat_writers: HashMap<&'static str, Arc<Mutex<csv::Writer<fs::File>>>>,
+    pub world: Arc<WorldStats>,
 }
 
 impl<O: Genome + Phenome + 'static> Window<O> {
-    fn new(report_fn: ReportFn<O>, config: Arc<Config>) -> Self {
+    fn new(report_fn: ReportFn<O>, config: Arc<Config>, world: Arc<WorldStats>) -> Self {
         let window_size = config.observer.window_size;
         let report_every = config.observer.report_every;
         assert!(window_size > 0, "window_size must be > 0");
@@ -111,8 +126,10 @@ impl<O: Genome + Phenome + 'static> Window<O> {
             report_fn,
             best: None,
             champion: None,
+            stage: config.fitness.stage(),
             archive: vec![],
             stat_writers,
+            world,
         }
     }
 
@@ -142,21 +159,58 @@ impl<O: Genome + Phenome + 'static> Window<O> {
         }
     }
 
-    fn insert(&mut self, thing: O) {
-        // Update the "best" seen so far, using scalar fitness measures
-        match &self.best {
-            None => self.best = Some(thing.clone()),
-            Some(champ) => {
-                if let (Some(champ_fit), Some(thing_fit)) = (
-                    champ.scalar_fitness(&self.config.fitness.weighting),
-                    thing.scalar_fitness(&self.config.fitness.weighting),
-                ) {
-                    if thing_fit < champ_fit {
-                        self.best = Some(thing.clone())
-                    }
-                }
+    /// Creatures scored under an earlier stage may still be in flight
+    /// when the stage advances, and their scores can't be compared with
+    /// those of the current stage.
+    fn is_stale(&self, thing: &O) -> bool {
+        !self.config.fitness.stages.is_empty()
+            && thing
+                .fitness()
+                .and_then(FitnessScore::stage)
+                .map_or(false, |s| s < self.config.fitness.stage())
+    }
+
+    /// Moves the island on to the next stage once the champion has met
+    /// the current stage's completion criterion. In reproducible mode, the
+    /// stage is advanced between evaluations instead (see
+    /// `Develop::evaluate`), since the observer's timing can't be relied on.
+    fn check_stage_completion(&mut self) {
+        if self.config.reproducible || self.config.fitness.is_final_stage() {
+            return;
+        }
+        if let Some(ref champion) = self.champion {
+            if champion.is_stage_complete(&self.config) {
+                dump_stage_champion(champion, &self.config);
+                self.report();
+                self.config.fitness.advance_stage();
+                self.keep_up_with_stage();
             }
         }
+    }
+
+    /// Clears out whatever was scored under an earlier stage, since none of
+    /// it can be compared with what comes next.
+    fn keep_up_with_stage(&mut self) {
+        let stage = self.config.fitness.stage();
+        if stage != self.stage {
+            self.stage = stage;
+            self.frame.clear();
+            self.i = 0;
+            self.best = None;
+            self.champion = None;
+        }
+    }
+
+    fn insert(&mut self, thing: O) {
+        self.keep_up_with_stage();
+        if self.is_stale(&thing) {
+            log::debug!("Ignoring creature scored under an earlier stage");
+            return;
+        }
+        // Update the "best" seen so far, using the fitness's own ordering
+        if self.best.is_none() || Self::improves_on(&thing, self.best.as_ref()) {
+            self.best = Some(thing.clone())
+        }
         // insert the incoming thing into the observation window
         self.i = (self.i + 1) % self.window_size;
         if self.frame.len() < self.window_size {
@@ -180,28 +234,27 @@ impl<O: Genome + Phenome + 'static> Window<O> {
             self.report();
         }
 
+        self.check_stage_completion();
         self.is_halting_condition_reached();
     }
 
+    /// Whether `thing` is strictly fitter than `incumbent`, by whatever
+    /// ordering the fitness representation defines. Under Pareto fitness,
+    /// this means that `thing` dominates `incumbent`.
+    fn improves_on(thing: &O, incumbent: Option<&O>) -> bool {
+        match (thing.fitness(), incumbent.and_then(Phenome::fitness)) {
+            (Some(_), None) => true,
+            (Some(t), Some(i)) => t.partial_cmp(i) == Some(Ordering::Less),
+            _ => false,
+        }
+    }
+
     fn update_best(&mut self) {
         let mut updated = false;
         for specimen in self.frame.iter() {
-            if let Some(f) = specimen.scalar_fitness(&self.config.fitness.weighting) {
-                match self.best.as_ref() {
-                    None => {
-                        updated = true;
-                        self.best = Some(specimen.clone())
-                    }
-                    Some(champ) => {
-                        if f < champ
-                            .scalar_fitness(&self.config.fitness.weighting)
-                            .expect("There should be a fitness score here")
-                        {
-                            updated = true;
-                            self.best = Some(specimen.clone())
-                        }
-                    }
-                }
+            if Self::improves_on(specimen, self.best.as_ref()) {
+                updated = true;
+                self.best = Some(specimen.clone())
             }
         }
 
@@ -217,7 +270,7 @@ impl<O: Genome + Phenome + 'static> Window<O> {
     fn update_champion(&mut self) {
         let mut updated = false;
         for specimen in self.frame.iter() {
-            if let Some(f) = specimen.scalar_fitness(&self.config.fitness.priority) {
+            if let Some(f) = specimen.scalar_fitness(self.config.fitness.priority()) {
                 match self.champion.as_ref() {
                     None => {
                         updated = true;
@@ -225,7 +278,7 @@ impl<O: Genome + Phenome + 'static> Window<O> {
                     }
                     Some(champ) => {
                         if f < champ
-                            .scalar_fitness(&self.config.fitness.priority)
+                            .scalar_fitness(self.config.fitness.priority())
                             .expect("there should be a fitness score here")
                         {
                             updated = true;
@@ -330,6 +383,24 @@ impl<O: Genome + Phenome + 'static> Window<O> {
     }
 }
 
+/// Dumps the creature that completed the current stage to the island's
+/// champions directory.
+pub fn dump_stage_champion<O: Phenome>(champion: &O, config: &Config) {
+    let stage = config.fitness.stage();
+    let path = format!(
+        "{}/champions/stage_{}_champion.json.gz",
+        config.data_directory(),
+        stage
+    );
+    log::info!(
+        "Island {}: stage {} complete, dumping champion to {}",
+        config.island_identifier,
+        stage,
+        path
+    );
+    dump(champion, &path).expect("failed to dump stage champion");
+}
+
 impl<O: 'static + Phenome + Genome> Observer<O> {
     /// The observe method should take a clone of the observable
     /// and store in something like a sliding observation window.
@@ -340,15 +411,20 @@ impl<O: 'static + Phenome + Genome> Observer<O> {
     pub fn spawn(config: &Config, report_fn: ReportFn<O>) -> Observer<O> {
         let (tx, rx): (Sender<O>, Receiver<O>) = channel();
 
+        let world = Arc::new(WorldStats {
+            population_size: AtomicUsize::new(config.pop_size),
+            radius: AtomicUsize::new(config.tournament.geographic_radius),
+        });
+        let window_world = world.clone();
         let config = Arc::new(config.clone());
         let handle: JoinHandle<()> = spawn(move || {
-            let mut window: Window<O> = Window::new(report_fn, config.clone());
+            let mut window: Window<O> = Window::new(report_fn, config.clone(), window_world);
             for observable in rx {
                 window.insert(observable);
             }
         });
 
-        Observer { handle, tx }
+        Observer { handle, tx, world }
     }
 
     // pub fn stop_evolution(&mut self) {
@@ -1,6 +1,8 @@
 use std::sync::Arc;
 
+use crate::configure::Config;
 use crate::evolution::Phenome;
+use crate::observer::dump_stage_champion;
 
 pub type FitnessFn<Pheno, State, Conf> =
     Box<dyn Fn(Pheno, &mut State, Arc<Conf>) -> Pheno + Sync + Send + 'static>;
@@ -23,4 +25,44 @@ pub trait Develop<P: Phenome> {
     fn apply_fitness_function(&mut self, ob: P) -> P;
 
     fn development_pipeline<I: 'static + Iterator<Item = P> + Send>(&self, inbound: I) -> Vec<P>;
+
+    /// Develops the phenotypes and applies the fitness function to each
+    /// of them. With `fitness.re_execute` set, phenotypes that have already
+    /// been developed are developed again.
+    ///
+    /// In reproducible mode, this is also where a completed stage is
+    /// advanced, so that it always happens at the same point in the run.
+    fn evaluate(&mut self, phenomes: Vec<P>, config: &Config) -> Vec<P>
+    where
+        P: 'static,
+    {
+        let phenomes = phenomes
+            .into_iter()
+            .map(|mut p| {
+                if config.fitness.re_execute {
+                    p.clear_development()
+                }
+                p
+            })
+            .collect::<Vec<P>>();
+        let phenomes = self
+            .development_pipeline(phenomes.into_iter())
+            .into_iter()
+            .map(|p| self.apply_fitness_function(p))
+            .collect::<Vec<P>>();
+        if config.reproducible {
+            advance_completed_stage(&phenomes, config);
+        }
+        phenomes
+    }
+}
+
+fn advance_completed_stage<P: Phenome>(phenomes: &[P], config: &Config) {
+    if config.fitness.is_final_stage() {
+        return;
+    }
+    if let Some(champion) = phenomes.iter().find(|p| p.is_stage_complete(config)) {
+        dump_stage_champion(champion, config);
+        config.fitness.advance_stage();
+    }
 }
@@ -1,8 +1,10 @@
+use std::sync::atomic::Ordering;
+
 use serde::Serialize;
 
-use crate::configure::Config;
+use crate::configure::{Config, MutationBias};
 use crate::emulator::profiler::{HasProfile, Profile};
-use crate::evolution::{Genome, Phenome};
+use crate::evolution::{GeneRole, Genome, Phenome};
 use crate::fitness::{MapFit, Weighted};
 use crate::get_epoch_counter;
 use crate::observer::Window;
@@ -11,6 +13,7 @@ use crate::observer::Window;
 pub struct StatRecord {
     pub counter: usize,
     pub epoch: usize,
+    pub stage: usize,
 
     pub length: f64,
     pub register_error: f64,
@@ -20,6 +23,32 @@ pub struct StatRecord {
     pub ratio_written: f64,
     pub emulation_time: f64,
     pub ratio_eligible: f64,
+    pub mean_gene_age: f64,
+    pub max_gene_age: usize,
+    pub population_size: usize,
+    pub radius: usize,
+    pub mutation_bias: MutationBias,
+    // The fractions of the mutated genes that went on to be executed,
+    // consumed as data, or never reached.
+    pub mutated_executed: f64,
+    pub mutated_consumed: f64,
+    pub mutated_unexecuted: f64,
+}
+
+fn mutated_role_ratios<C: Genome>(creatures: &[&C]) -> (f64, f64, f64) {
+    let roles = creatures
+        .iter()
+        .flat_map(|c| c.mutated_roles())
+        .collect::<Vec<GeneRole>>();
+    if roles.is_empty() {
+        return (0.0, 0.0, 0.0);
+    }
+    let ratio = |role| roles.iter().filter(|r| **r == role).count() as f64 / roles.len() as f64;
+    (
+        ratio(GeneRole::Executed),
+        ratio(GeneRole::Consumed),
+        ratio(GeneRole::Unexecuted),
+    )
 }
 
 impl StatRecord {
@@ -32,10 +61,10 @@ impl StatRecord {
         }
         // let specimen_exec_ratio = specimen.execution_ratio();
         let specimen_scalar_fitness = specimen
-            .scalar_fitness(&window.config.fitness.weighting)
+            .scalar_fitness(window.config.fitness.weighting())
             .unwrap_or(1.0);
         let specimen_priority_fitness = specimen
-            .scalar_fitness(&window.config.fitness.priority)
+            .scalar_fitness(window.config.fitness.priority())
             .unwrap_or(1.0);
         let specimen_register_error = specimen
             .fitness()
@@ -57,10 +86,20 @@ impl StatRecord {
             .as_ref()
             .map(|p| p.avg_emulation_micros())
             .unwrap_or(0.0);
+        let ages = specimen.gene_ages().unwrap_or(&[]);
+        let specimen_mean_gene_age = if ages.is_empty() {
+            0.0
+        } else {
+            ages.iter().sum::<usize>() as f64 / ages.len() as f64
+        };
+        let specimen_max_gene_age = ages.iter().max().cloned().unwrap_or(0);
+        let (mutated_executed, mutated_consumed, mutated_unexecuted) =
+            mutated_role_ratios(&[specimen]);
 
         Self {
             counter,
             epoch,
+            stage: window.config.fitness.stage(),
             length: specimen_len,
             register_error: specimen_register_error,
             scalar_fitness: specimen_scalar_fitness,
@@ -69,6 +108,14 @@ impl StatRecord {
             ratio_written: specimen_ratio_written,
             emulation_time: specimen_emulation_time,
             ratio_eligible: 1.0,
+            mean_gene_age: specimen_mean_gene_age,
+            max_gene_age: specimen_max_gene_age,
+            population_size: window.world.population_size.load(Ordering::Relaxed),
+            radius: window.world.radius.load(Ordering::Relaxed),
+            mutation_bias: window.config.mutation_bias,
+            mutated_executed,
+            mutated_consumed,
+            mutated_unexecuted,
         }
     }
 
@@ -99,12 +146,12 @@ impl StatRecord {
 
         let scalar_fitness: f64 = frame
             .iter()
-            .filter_map(|g| g.scalar_fitness(&window.config.fitness.weighting))
+            .filter_map(|g| g.scalar_fitness(window.config.fitness.weighting()))
             .sum::<f64>()
             / frame.len() as f64;
         let priority_fitness: f64 = frame
             .iter()
-            .filter_map(|g| g.scalar_fitness(&window.config.fitness.priority))
+            .filter_map(|g| g.scalar_fitness(window.config.fitness.priority()))
             .sum::<f64>()
             / frame.len() as f64;
         let fitnesses = frame.iter().filter_map(|g| g.fitness()).collect::<Vec<_>>();
@@ -122,9 +169,24 @@ impl StatRecord {
             .sum::<f64>()
             / frame.len() as f64;
 
+        let ages = frame
+            .iter()
+            .filter_map(|g| g.gene_ages())
+            .flatten()
+            .cloned()
+            .collect::<Vec<usize>>();
+        let mean_gene_age = if ages.is_empty() {
+            0.0
+        } else {
+            ages.iter().sum::<usize>() as f64 / ages.len() as f64
+        };
+        let max_gene_age = ages.iter().max().cloned().unwrap_or(0);
+        let (mutated_executed, mutated_consumed, mutated_unexecuted) = mutated_role_ratios(frame);
+
         StatRecord {
             counter,
             epoch: get_epoch_counter(),
+            stage: window.config.fitness.stage(),
             length,
             scalar_fitness,
             priority_fitness,
@@ -133,6 +195,14 @@ impl StatRecord {
             ratio_written: fit_vec.get("mem_write_ratio").unwrap_or_default(),
             emulation_time,
             ratio_eligible,
+            mean_gene_age,
+            max_gene_age,
+            population_size: window.world.population_size.load(Ordering::Relaxed),
+            radius: window.world.radius.load(Ordering::Relaxed),
+            mutation_bias: window.config.mutation_bias,
+            mutated_executed,
+            mutated_consumed,
+            mutated_unexecuted,
         }
     }
 }
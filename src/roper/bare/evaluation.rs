@@ -1,9 +1,8 @@
-use std::convert::TryInto;
 use std::sync::Arc;
 
 use unicorn::Cpu;
 
-use crate::emulator::register_pattern::{Register, UnicornRegisterState};
+use crate::emulator::register_pattern::Register;
 use crate::ontogenesis::FitnessFn;
 use crate::roper::Sketches;
 use crate::{
@@ -23,6 +22,7 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
     pub fn spawn(config: &Config, fitness_fn: FitnessFn<Creature, Sketches, Config>) -> Self {
         let mut config = config.clone();
         config.roper.parse_register_pattern();
+        config.roper.parse_memory_pattern();
         let hatch_config = Arc::new(config.roper.clone());
         let register_pattern = config.roper.register_pattern();
         let output_registers: Vec<Register<C>> = {
@@ -33,22 +33,41 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
                 .map(|s| s.parse().ok().expect("Failed to parse output register"))
                 .collect::<Vec<_>>();
             if let Some(pat) = register_pattern {
-                let arch_specific_pat: UnicornRegisterState<C> =
-                    pat.try_into().expect("Failed to parse register pattern");
-                let regs_in_pat = arch_specific_pat.0.keys().cloned().collect::<Vec<_>>();
-                out_reg.extend_from_slice(&regs_in_pat);
-                out_reg.dedup();
-                out_reg
-            } else {
-                out_reg
-                //todo!("implement a conversion method from problem sets to register maps");
+                // this includes any registers that the pattern's targets refer to
+                for r in pat.registers() {
+                    let reg: Register<C> = r
+                        .parse()
+                        .ok()
+                        .expect("Failed to parse register in pattern");
+                    if !out_reg.contains(&reg) {
+                        out_reg.push(reg);
+                    }
+                }
             }
+            if let Some(r) = config
+                .roper
+                .memory_pattern()
+                .and_then(|pat| pat.register.as_ref())
+            {
+                let reg: Register<C> = r
+                    .parse()
+                    .ok()
+                    .expect("Failed to parse register in memory pattern");
+                if !out_reg.contains(&reg) {
+                    out_reg.push(reg);
+                }
+            }
+            out_reg
         };
         let inputs = if config.roper.randomize_registers {
-            vec![util::architecture::random_register_state::<u64, C>(
-                &output_registers,
-                config.random_seed,
-            )]
+            (0..config.roper.num_inputs)
+                .map(|i| {
+                    util::architecture::random_register_state::<u64, C>(
+                        &output_registers,
+                        config.random_seed ^ i as u64,
+                    )
+                })
+                .collect::<Vec<_>>()
         } else {
             vec![util::architecture::constant_register_state::<C>(
                 &output_registers,
@@ -87,7 +106,14 @@ impl<'a, C: 'static + Cpu<'static>> Develop<Creature> for Evaluator<C> {
         }
     }
 
-    fn apply_fitness_function(&mut self, creature: Creature) -> Creature {
+    fn apply_fitness_function(&mut self, mut creature: Creature) -> Creature {
+        if let Some(profile) = creature.profile.as_ref() {
+            let executed = &profile.gadgets_executed;
+            creature
+                .chromosome
+                .refresh_genes(|word| executed.contains(word));
+            creature.chromosome.roles = profile.word_roles(&creature.chromosome.chromosome);
+        }
         (self.fitness_fn)(creature, &mut self.sketches, self.config.clone())
     }
 
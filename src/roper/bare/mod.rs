@@ -2,21 +2,22 @@ use std::fmt;
 use std::hash::{Hash, Hasher};
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
-use hashbrown::HashMap;
-use rand::seq::IteratorRandom;
-use rand::{thread_rng, Rng};
-use rand_distr::{Distribution, Standard};
+use hashbrown::{HashMap, HashSet};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-use crate::configure::Config;
+use crate::configure::{Config, Distance};
 use crate::emulator::loader;
 use crate::emulator::loader::get_static_memory_image;
 use crate::emulator::pack::Pack;
 use crate::emulator::profiler::{HasProfile, Profile};
-use crate::evolution::{Genome, LinearChromosome, Mutation, Phenome};
+use crate::emulator::register_pattern::RegisterCase;
+use crate::evolution::{Gene, GeneRole, Genome, LinearChromosome, Mutation, Phenome};
 use crate::roper::Fitness;
 use crate::util::architecture::{read_integer, write_integer, Perms};
-use crate::util::random::hash_seed_rng;
+use crate::util::random::{hash_seed_rng, Prng};
 use crate::util::{self, architecture::Endian};
 
 /// The `evaluation` module contains the various fitness functions, and the construction
@@ -40,6 +41,80 @@ pub struct Creature {
     pub description: Option<String>,
 }
 
+impl Creature {
+    /// The positions in the chromosome at which a gadget's frame begins,
+    /// and so where it can be cut without separating a gadget from the
+    /// stack words it consumes. For a creature that's been executed, these
+    /// are the positions of the gadgets it executed, followed by that of
+    /// the first gadget after them that wasn't, where execution went astray
+    /// (or the end of the chromosome). Otherwise, they're the positions of
+    /// all the words that point into executable memory.
+    fn frame_boundaries(&self) -> Vec<usize> {
+        let memory = loader::get_static_memory_image();
+        let is_gadget = |w: &u64| {
+            memory
+                .perm_of_addr(*w)
+                .map(|p| p.intersects(Perms::EXEC))
+                .unwrap_or(false)
+        };
+        let chromosome = self.chromosome();
+        match self.profile.as_ref() {
+            Some(profile) if profile.executable => {
+                let roles = profile.word_roles(chromosome);
+                let mut boundaries = (0..roles.len())
+                    .filter(|i| roles[*i] == GeneRole::Executed)
+                    .collect::<Vec<usize>>();
+                let after = boundaries.last().map_or(0, |i| i + 1);
+                boundaries.push(
+                    (after..roles.len())
+                        .find(|i| roles[*i] == GeneRole::Unexecuted)
+                        .unwrap_or_else(|| roles.len()),
+                );
+                boundaries
+            }
+            _ => (0..chromosome.len())
+                .filter(|i| is_gadget(&chromosome[*i]))
+                .collect(),
+        }
+    }
+
+    /// Splices the executed prefix of one mate, cut at one of its gadget
+    /// boundaries, onto the tail of another, cut where one of its gadgets
+    /// begins, so that the working part of the first chain is kept whole.
+    /// Returns `None` if no mate has been executed successfully.
+    fn trace_crossover(
+        mates: &[&Self],
+        config: &Config,
+        rng: &mut Prng,
+    ) -> Option<LinearChromosome<u64, WordMutation>> {
+        let (i, prefix) = mates
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.profile.as_ref().map_or(false, |p| p.executable))
+            .choose(rng)?;
+        let suffix = mates[(i + rng.gen_range(1, mates.len())) % mates.len()];
+        let prefix_cuts = prefix
+            .frame_boundaries()
+            .into_iter()
+            .filter(|c| *c > 0)
+            .collect::<Vec<usize>>();
+        let prefix_len = *prefix_cuts.choose(rng)?;
+        let suffix_start = suffix
+            .frame_boundaries()
+            .choose(rng)
+            .cloned()
+            .unwrap_or_else(|| rng.gen_range(0, suffix.len().max(1)));
+        Some(LinearChromosome::splice(
+            &prefix.chromosome,
+            prefix_len,
+            &suffix.chromosome,
+            suffix_start,
+            config,
+            rng,
+        ))
+    }
+}
+
 impl HasProfile for Creature {
     fn profile(&self) -> Option<&Profile> {
         self.profile.as_ref()
@@ -230,6 +305,8 @@ impl Genome for Creature {
             chromosome: LinearChromosome {
                 chromosome,
                 mutations: vec![None; len],
+                ages: vec![0; len],
+                roles: vec![GeneRole::Unknown; len],
                 parentage: vec![],
                 parent_names: vec![],
                 name,
@@ -245,15 +322,51 @@ impl Genome for Creature {
         }
     }
 
-    fn crossover(mates: &[&Self], config: &Config) -> Self {
-        let parents = mates
-            .iter()
-            .map(|x| &x.chromosome)
-            .collect::<Vec<&LinearChromosome<_, _>>>();
-        let chromosome = LinearChromosome::crossover(&parents, config);
+    fn from_chromosome<H: Hash>(chromosome: Vec<u64>, config: &Config, salt: H) -> Option<Self> {
+        let len = chromosome.len();
+        let mut hasher = fnv::FnvHasher::default();
+        salt.hash(&mut hasher);
+        config.random_seed.hash(&mut hasher);
+        let tag = hash_seed_rng(&hasher.finish()).gen::<u64>();
+        Some(Self {
+            chromosome: LinearChromosome {
+                chromosome,
+                mutations: vec![None; len],
+                ages: vec![0; len],
+                roles: vec![GeneRole::Unknown; len],
+                parentage: vec![],
+                parent_names: vec![],
+                name: util::name::random(4, &salt),
+                generation: 0,
+            },
+            tag,
+            profile: None,
+            fitness: None,
+            front: None,
+            num_offspring: 0,
+            native_island: config.island_identifier,
+            description: None,
+        })
+    }
+
+    fn crossover(mates: &[&Self], config: &Config, rng: &mut Prng) -> Self {
+        let trace_crossover =
+            mates.len() > 1 && rng.gen_range(0.0, 1.0) < config.roper.trace_crossover_rate;
+        let spliced = if trace_crossover {
+            Self::trace_crossover(mates, config, rng)
+        } else {
+            None
+        };
+        let chromosome = spliced.unwrap_or_else(|| {
+            let parents = mates
+                .iter()
+                .map(|x| &x.chromosome)
+                .collect::<Vec<&LinearChromosome<_, _>>>();
+            LinearChromosome::crossover(&parents, config, rng)
+        });
         Self {
             chromosome,
-            tag: thread_rng().gen::<u64>(),
+            tag: rng.gen::<u64>(),
             profile: None,
             fitness: None,
             front: None,
@@ -263,13 +376,25 @@ impl Genome for Creature {
         }
     }
 
-    fn mutate(&mut self, config: &Config) {
-        self.chromosome.mutate(config)
+    fn mutate(&mut self, config: &Config, rng: &mut Prng) {
+        self.chromosome.mutate(config, rng)
     }
 
     fn incr_num_offspring(&mut self, n: usize) {
         self.num_offspring += n
     }
+
+    fn gene_ages(&self) -> Option<&[usize]> {
+        Some(&self.chromosome.ages)
+    }
+
+    fn mutated_roles(&self) -> Vec<GeneRole> {
+        self.chromosome.mutated_roles()
+    }
+
+    fn generation(&self) -> usize {
+        self.chromosome.generation
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash)]
@@ -279,18 +404,87 @@ pub enum WordMutation {
     AddressAdd,
     AddressSub,
     BitFlip,
+    SoupGadget,
+    SimilarGadget,
+    InsertGadget,
+    DeleteWord,
+    DuplicateRun,
+    SwapWords,
+}
+
+/// The mutations that act on a single word, in the order of their weights
+/// in `WordMutationConfig::point_weights`.
+const POINT_MUTATIONS: [WordMutation; 7] = [
+    WordMutation::Dereference,
+    WordMutation::Indirection,
+    WordMutation::AddressAdd,
+    WordMutation::AddressSub,
+    WordMutation::BitFlip,
+    WordMutation::SoupGadget,
+    WordMutation::SimilarGadget,
+];
+
+/// The longest run of words that `DuplicateRun` will copy.
+const MAX_DUPLICATED_RUN: usize = 8;
+
+/// How many soup gadgets `SimilarGadget` will look at in search of one
+/// like the gadget it's replacing.
+const SIMILARITY_SAMPLES: usize = 64;
+
+fn random_soup_gadget<R: Rng>(config: &Config, rng: &mut R) -> Option<u64> {
+    config
+        .roper
+        .soup
+        .as_ref()
+        .and_then(|soup| soup.choose(rng).copied())
+}
+
+/// A crude summary of what a gadget does: the id of its first instruction,
+/// and the registers that instruction writes.
+fn gadget_signature(address: u64) -> Option<(u64, Vec<u64>)> {
+    let memory = get_static_memory_image();
+    let disassembler = memory.disasm.as_ref()?;
+    let code = memory.try_dereference(address, None)?;
+    let insts = disassembler.disas(code, address, Some(1)).ok()?;
+    let inst = insts.iter().next()?;
+    let regs_written = disassembler
+        .insn_detail(&inst)
+        .map(|details| details.regs_write().map(|reg| reg.0 as u64).collect())
+        .unwrap_or_default();
+    Some((inst.id().0 as u64, regs_written))
+}
+
+/// Looks through a sample of the soup for a gadget like the one at
+/// `address`, preferring one whose signature matches exactly, but settling
+/// for one that begins with the same kind of instruction.
+fn similar_gadget<R: Rng>(address: u64, config: &Config, rng: &mut R) -> Option<u64> {
+    let (id, regs_written) = gadget_signature(address)?;
+    let soup = config.roper.soup.as_ref()?;
+    soup.choose_multiple(rng, SIMILARITY_SAMPLES)
+        .filter(|g| **g != address)
+        .filter_map(|g| {
+            let (other_id, other_regs) = gadget_signature(*g)?;
+            match (other_id == id, other_regs == regs_written) {
+                (true, true) => Some((2, *g)),
+                (true, false) => Some((1, *g)),
+                _ => None,
+            }
+        })
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, g)| g)
 }
 
 impl Mutation for WordMutation {
     type Allele = u64;
 
-    fn mutate_point(allele: &mut Self::Allele, _config: &Config) -> Self {
-        let mut rng = thread_rng();
-        let mutation = rand::random::<WordMutation>();
+    fn mutate_point(allele: &mut Self::Allele, config: &Config, rng: &mut Prng) -> Self {
+        let weights = config.roper.word_mutation.point_weights();
+        let mutation = POINT_MUTATIONS[WeightedIndex::new(&weights)
+            .expect("Bad word mutation weights")
+            .sample(rng)];
         let memory = get_static_memory_image();
         let endian = memory.endian;
         let word_size = memory.word_size;
-        // TODO: add a mutation that picks a random address from the soup
         match mutation {
             WordMutation::Dereference => {
                 if let Some(bytes) = memory.try_dereference(*allele, None) {
@@ -321,28 +515,96 @@ impl Mutation for WordMutation {
                 let word = *allele ^ (1 << rng.gen_range(0, word_size as u64 * 8));
                 *allele = word;
             }
+            WordMutation::SoupGadget => {
+                if let Some(gadget) = random_soup_gadget(config, rng) {
+                    *allele = gadget;
+                }
+            }
+            WordMutation::SimilarGadget => {
+                if let Some(gadget) = similar_gadget(*allele, config, rng) {
+                    *allele = gadget;
+                }
+            }
+            WordMutation::InsertGadget
+            | WordMutation::DeleteWord
+            | WordMutation::DuplicateRun
+            | WordMutation::SwapWords => {
+                unreachable!("{:?} is not a point mutation", mutation)
+            }
         }
         mutation
     }
-}
 
-impl Distribution<WordMutation> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> WordMutation {
-        use WordMutation::*;
-        match rng.gen_range(0, 5) {
-            0 => Dereference,
-            1 => Indirection,
-            2 => AddressAdd,
-            3 => AddressSub,
-            4 => BitFlip,
-            n => unreachable!("no, can't get {}", n),
+    fn mutate_structure(
+        chromosome: &[Self::Allele],
+        config: &Config,
+        rng: &mut Prng,
+    ) -> Option<(Vec<Gene<Self::Allele>>, Self)> {
+        let rates = &config.roper.word_mutation;
+        let roll = rng.gen_range(0.0, 1.0);
+        let mut threshold = 0.0;
+        let mutation = [
+            (WordMutation::InsertGadget, rates.insert_gadget),
+            (WordMutation::DeleteWord, rates.delete_word),
+            (WordMutation::DuplicateRun, rates.duplicate_run),
+            (WordMutation::SwapWords, rates.swap_words),
+        ]
+        .iter()
+        .find_map(|(mutation, rate)| {
+            threshold += rate;
+            if roll < threshold {
+                Some(*mutation)
+            } else {
+                None
+            }
+        })?;
+
+        let len = chromosome.len();
+        let mut genes = (0..len).map(Gene::Kept).collect::<Vec<_>>();
+        match mutation {
+            WordMutation::InsertGadget => {
+                let gadget = random_soup_gadget(config, rng)?;
+                genes.insert(rng.gen_range(0, len + 1), Gene::New(gadget));
+            }
+            WordMutation::DeleteWord => {
+                if len < 2 {
+                    return None;
+                }
+                let i = rng.gen_range(0, len);
+                genes.remove(i);
+                // mark the word that takes its place
+                if i < genes.len() {
+                    genes[i] = Gene::Moved(i + 1);
+                }
+            }
+            WordMutation::DuplicateRun => {
+                if len == 0 {
+                    return None;
+                }
+                let start = rng.gen_range(0, len);
+                let end = start + rng.gen_range(1, (len - start).min(MAX_DUPLICATED_RUN) + 1);
+                let tail = genes.split_off(end);
+                genes.extend((start..end).map(Gene::Moved));
+                genes.extend(tail);
+            }
+            WordMutation::SwapWords => {
+                if len < 2 {
+                    return None;
+                }
+                let i = rng.gen_range(0, len);
+                let j = (i + rng.gen_range(1, len)) % len;
+                genes[i] = Gene::Moved(j);
+                genes[j] = Gene::Moved(i);
+            }
+            _ => unreachable!("{:?} is not a structural mutation", mutation),
         }
+        Some((genes, mutation))
     }
 }
 
 impl Phenome for Creature {
     type Fitness = Fitness<'static>;
-    type Problem = ();
+    type Problem = RegisterCase;
 
     fn generate_description(&mut self) {
         self.description = Some(format!("{:#?}", self))
@@ -391,17 +653,185 @@ impl Phenome for Creature {
     }
 
     fn is_goal_reached(&self, config: &Config) -> bool {
-        self.scalar_fitness(&config.fitness.priority)
-            .map(|p| p - config.fitness.target <= std::f64::EPSILON)
-            .unwrap_or(false)
+        config.fitness.is_final_stage() && self.is_stage_complete(config)
     }
 
-    fn fails(&self, _case: &Self::Problem) -> bool {
-        unimplemented!("putting lexicase on ice for now")
-        // !case.check_creature(self)
+    fn fails(&self, case: &Self::Problem) -> bool {
+        self.case_error(case) > 0.0
+    }
+
+    fn case_error(&self, case: &Self::Problem) -> f64 {
+        self.profile.as_ref().map_or(1.0, |p| case.error(p))
     }
 
     fn mature(&self) -> bool {
         self.profile.is_some()
     }
+
+    fn clear_development(&mut self) {
+        self.profile = None
+    }
+
+    fn gadgets_executed(&self) -> Option<&HashSet<u64>> {
+        self.profile.as_ref().map(|p| &p.gadgets_executed)
+    }
+
+    fn behavioural_distance(&self, other: &Self, measure: Distance) -> f64 {
+        self.profile_distance(other, measure)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::WordMutationConfig;
+
+    use super::*;
+
+    fn structural_config(rates: WordMutationConfig) -> Config {
+        let mut config = Config::default();
+        config.roper.word_mutation = rates;
+        config.roper.soup = Some(vec![0xbeef]);
+        config
+    }
+
+    /// The indices of the original chromosome that each gene refers to.
+    fn sources(genes: &[Gene<u64>]) -> Vec<Option<usize>> {
+        genes
+            .iter()
+            .map(|gene| match gene {
+                Gene::Kept(i) | Gene::Moved(i) => Some(*i),
+                Gene::New(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_no_structural_mutation() {
+        // structural mutations are all switched off by default
+        let config = structural_config(WordMutationConfig::default());
+        let mut rng = hash_seed_rng(&0);
+        let chromosome = vec![1, 2, 3, 4];
+        assert!(WordMutation::mutate_structure(&chromosome, &config, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_insert_gadget() {
+        let config = structural_config(WordMutationConfig {
+            insert_gadget: 1.0,
+            ..Default::default()
+        });
+        let chromosome = vec![1, 2, 3, 4];
+        for seed in 0..32 {
+            let mut rng = hash_seed_rng(&seed);
+            let (genes, mutation) =
+                WordMutation::mutate_structure(&chromosome, &config, &mut rng).unwrap();
+            assert!(matches!(mutation, WordMutation::InsertGadget));
+            assert_eq!(genes.len(), chromosome.len() + 1);
+            let i = genes
+                .iter()
+                .position(|g| *g == Gene::New(0xbeef))
+                .expect("no gadget inserted");
+            let mut kept = genes.clone();
+            kept.remove(i);
+            assert_eq!(kept, (0..4).map(Gene::Kept).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_delete_word() {
+        let config = structural_config(WordMutationConfig {
+            delete_word: 1.0,
+            ..Default::default()
+        });
+        let chromosome = vec![1, 2, 3, 4];
+        for seed in 0..32 {
+            let mut rng = hash_seed_rng(&seed);
+            let (genes, mutation) =
+                WordMutation::mutate_structure(&chromosome, &config, &mut rng).unwrap();
+            assert!(matches!(mutation, WordMutation::DeleteWord));
+            assert_eq!(genes.len(), chromosome.len() - 1);
+            let deleted = (0..4)
+                .find(|i| !sources(&genes).contains(&Some(*i)))
+                .expect("no word deleted");
+            for (j, gene) in genes.iter().enumerate() {
+                let expected = match j {
+                    j if j < deleted => Gene::Kept(j),
+                    j if j == deleted => Gene::Moved(j + 1),
+                    j => Gene::Kept(j + 1),
+                };
+                assert_eq!(*gene, expected);
+            }
+        }
+        let mut rng = hash_seed_rng(&0);
+        assert!(WordMutation::mutate_structure(&[1], &config, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_run() {
+        let config = structural_config(WordMutationConfig {
+            duplicate_run: 1.0,
+            ..Default::default()
+        });
+        let chromosome = (0..20).collect::<Vec<u64>>();
+        for seed in 0..32 {
+            let mut rng = hash_seed_rng(&seed);
+            let (genes, mutation) =
+                WordMutation::mutate_structure(&chromosome, &config, &mut rng).unwrap();
+            assert!(matches!(mutation, WordMutation::DuplicateRun));
+            // the copy follows the run it was copied from
+            let start = genes
+                .iter()
+                .position(|g| matches!(g, Gene::Moved(_)))
+                .expect("nothing duplicated");
+            let run = genes.len() - chromosome.len();
+            assert!((1..=MAX_DUPLICATED_RUN).contains(&run));
+            let copied_from = start - run;
+            for (j, gene) in genes.iter().enumerate() {
+                let expected = if j < start {
+                    Gene::Kept(j)
+                } else if j < start + run {
+                    Gene::Moved(copied_from + j - start)
+                } else {
+                    Gene::Kept(j - run)
+                };
+                assert_eq!(*gene, expected);
+            }
+        }
+        let mut rng = hash_seed_rng(&0);
+        assert!(WordMutation::mutate_structure(&[], &config, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_swap_words() {
+        let config = structural_config(WordMutationConfig {
+            swap_words: 1.0,
+            ..Default::default()
+        });
+        let chromosome = vec![1, 2, 3, 4];
+        for seed in 0..32 {
+            let mut rng = hash_seed_rng(&seed);
+            let (genes, mutation) =
+                WordMutation::mutate_structure(&chromosome, &config, &mut rng).unwrap();
+            assert!(matches!(mutation, WordMutation::SwapWords));
+            assert_eq!(genes.len(), chromosome.len());
+            let moved = genes
+                .iter()
+                .enumerate()
+                .filter_map(|(j, g)| match g {
+                    Gene::Moved(i) => Some((j, *i)),
+                    Gene::Kept(i) => {
+                        assert_eq!(*i, j);
+                        None
+                    }
+                    Gene::New(_) => panic!("no genes should be new"),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(moved.len(), 2);
+            let (i, j) = moved[0];
+            assert_ne!(i, j);
+            assert_eq!(moved[1], (j, i));
+        }
+        let mut rng = hash_seed_rng(&0);
+        assert!(WordMutation::mutate_structure(&[1], &config, &mut rng).is_none());
+    }
 }
@@ -0,0 +1,76 @@
+use crate::configure::{Config, Feature, FeatureDimension};
+use crate::emulator::profiler::Profile;
+use crate::emulator::register_pattern::RegisterCase;
+
+/// Measures the feature dimensions of the MAP-Elites archive from a
+/// creature's profile.
+#[derive(Clone, Debug)]
+pub struct Descriptor {
+    dimensions: Vec<FeatureDimension>,
+    /// The fitness cases for each register in the register pattern, in
+    /// alphabetical order of register.
+    register_cases: Vec<Vec<RegisterCase>>,
+}
+
+impl Descriptor {
+    pub fn new(config: &Config) -> Self {
+        let mut config = config.clone();
+        config.roper.parse_register_pattern();
+        let num_inputs = config.roper.num_inputs;
+        let register_cases = config
+            .roper
+            .register_pattern()
+            .map(|pattern| {
+                let mut registers = pattern.0.keys().cloned().collect::<Vec<String>>();
+                registers.sort();
+                registers
+                    .into_iter()
+                    .map(|register| pattern.restricted_to(&[register]).cases(num_inputs))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let dimensions = config.map_elites.dimensions.clone();
+        if dimensions
+            .iter()
+            .any(|dim| dim.feature == Feature::RegistersMatched)
+            && register_cases.is_empty()
+        {
+            log::warn!("The RegistersMatched feature needs a register pattern");
+        }
+        Self {
+            dimensions,
+            register_cases,
+        }
+    }
+
+    pub fn describe(&self, profile: &Profile) -> Vec<f64> {
+        self.dimensions
+            .iter()
+            .map(|dim| match dim.feature {
+                Feature::GadgetsExecuted => profile.gadgets_executed.len() as f64,
+                Feature::ChainLength => {
+                    profile.paths.iter().map(Vec::len).max().unwrap_or(0) as f64
+                }
+                Feature::Syscalls => profile
+                    .syscall_registers
+                    .iter()
+                    .map(Vec::len)
+                    .max()
+                    .unwrap_or(0) as f64,
+                Feature::RegistersMatched => self.registers_matched(profile) as f64,
+            })
+            .collect()
+    }
+
+    /// A register counts as matched if every feature of its target is met
+    /// on every input.
+    fn registers_matched(&self, profile: &Profile) -> u64 {
+        self.register_cases
+            .iter()
+            .enumerate()
+            .filter(|(_, cases)| {
+                !cases.is_empty() && cases.iter().all(|case| case.error(profile) == 0.0)
+            })
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+}
@@ -0,0 +1,386 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use hashbrown::HashSet;
+
+use crate::configure::Config;
+use crate::emulator::loader::get_static_memory_image;
+use crate::emulator::profiler::{HasProfile, Profile};
+use crate::emulator::register_pattern::RegisterPattern;
+use crate::evolution::Phenome;
+use crate::fitness::Weighted;
+use crate::ontogenesis::FitnessFn;
+use crate::roper::novelty::Behaviour;
+use crate::roper::Sketches;
+use crate::util::entropy::Entropy;
+
+/// A fitness component measures one or more named scores from a creature's
+/// profile, and records them in its fitness. The weighting expression is
+/// what combines them.
+pub type Component = fn(&Profile, &mut Sketches, &Config, &mut Weighted<'static>);
+
+/// Looks up a fitness component by name.
+pub fn component(name: &str) -> Option<Component> {
+    let component: Component = match name {
+        "register_error" => register_error,
+        "no_syscall" => no_syscall,
+        "register_novelty" => register_novelty,
+        "register_state_novelty" => register_state_novelty,
+        "novelty" => novelty,
+        "string_error" => string_error,
+        "pointer_error" => pointer_error,
+        "mem_write_novelty" => mem_write_novelty,
+        "mem_write_ratio" => mem_write_ratio,
+        "crash_count" => crash_count,
+        "gadgets_executed" => gadgets_executed,
+        "code_coverage" => code_coverage,
+        "entropy" => entropy,
+        "zeroes" => zeroes,
+        _ => return None,
+    };
+    Some(component)
+}
+
+/// Resolves the names of the components once, and returns a fitness
+/// function that runs each of them in turn.
+pub fn components_fitness_function<P>(names: &[String]) -> FitnessFn<P, Sketches, Config>
+where
+    P: 'static + HasProfile + Phenome<Fitness = Weighted<'static>> + Sized,
+{
+    assert!(!names.is_empty(), "No fitness components listed");
+    let components = names
+        .iter()
+        .map(|name| {
+            component(name).unwrap_or_else(|| panic!("No such fitness component as {}", name))
+        })
+        .collect::<Vec<Component>>();
+    Box::new(move |creature: P, sketch: &mut Sketches, config: Arc<Config>| {
+        apply_components(creature, &components, sketch, &config)
+    })
+}
+
+pub fn apply_components<P>(
+    mut creature: P,
+    components: &[Component],
+    sketch: &mut Sketches,
+    config: &Config,
+) -> P
+where
+    P: HasProfile + Phenome<Fitness = Weighted<'static>> + Sized,
+{
+    if let Some(profile) = creature.profile() {
+        let mut fitness = Weighted::from_config(&config.fitness);
+        for component in components {
+            component(profile, sketch, config, &mut fitness);
+        }
+        creature.set_fitness(fitness);
+    }
+    creature
+}
+
+/// The register pattern, restricted to whichever registers the current
+/// stage is concerned with.
+fn stage_register_pattern(config: &Config) -> Option<Cow<'_, RegisterPattern>> {
+    let pattern = config.roper.register_pattern()?;
+    Some(match config.fitness.registers() {
+        Some(registers) => Cow::Owned(pattern.restricted_to(registers)),
+        None => Cow::Borrowed(pattern),
+    })
+}
+
+/// The distance from the register pattern. The pattern is also checked
+/// against the register states snapshotted at each syscall, so that a
+/// chain that sets up its syscall correctly isn't penalized for whatever
+/// happens afterwards.
+pub fn register_error(
+    profile: &Profile,
+    _sketch: &mut Sketches,
+    config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    if let Some(pattern) = stage_register_pattern(config) {
+        // assuming that when the register pattern task is activated, there's only one register state
+        // to worry about. this may need to be adjusted in the future. bit sloppy now.
+        let register_error = profile
+            .register_states(0)
+            .map(|(r, memory)| pattern.distance_from_register_state(r, memory))
+            .fold(std::f64::MAX, f64::min);
+        fitness.insert("register_error", register_error);
+    } else {
+        log::error!("No register pattern?");
+    }
+}
+
+pub fn no_syscall(
+    profile: &Profile,
+    _sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    let no_syscall = match profile.syscall_registers.get(0) {
+        Some(snapshots) if !snapshots.is_empty() => 0.0,
+        _ => 1.0,
+    };
+    fitness.insert("no_syscall", no_syscall);
+}
+
+/// The novelty of the ways in which the registers miss the pattern.
+pub fn register_novelty(
+    profile: &Profile,
+    sketch: &mut Sketches,
+    config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    if let Some(pattern) = stage_register_pattern(config) {
+        let iter = profile
+            .registers
+            .iter()
+            .zip(profile.writeable_memory.iter())
+            .map(|(r, m)| pattern.incorrect_register_states(r, Some(m.as_slice())))
+            .flatten()
+            .map(|p| {
+                sketch.register_error.insert(p);
+                sketch.register_error.query(p)
+            });
+        fitness.insert("register_novelty", stats::mean(iter));
+    } else {
+        log::error!("No register pattern?");
+    }
+}
+
+/// The novelty of the register states themselves, with no regard for
+/// any pattern.
+pub fn register_state_novelty(
+    profile: &Profile,
+    sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    fitness.insert(
+        "register_state_novelty",
+        register_state_frequency(profile, sketch),
+    );
+}
+
+/// The mean frequency of each register's final value and referential
+/// chain.
+pub fn register_state_frequency(profile: &Profile, sketch: &mut Sketches) -> f64 {
+    let mut scores = vec![];
+    for reg_state in &profile.registers {
+        for (reg, vals) in reg_state.0.iter() {
+            sketch.register_error.insert((reg, vals));
+            scores.push(sketch.register_error.query((reg, vals)));
+        }
+    }
+    stats::mean(scores.into_iter())
+}
+
+/// Behavioural novelty, measured as the sparseness of the creature's
+/// behaviour among the archive and the recently evaluated. Since lower
+/// scores are better, this is recorded as `1 / (1 + sparseness)`.
+pub fn novelty(
+    profile: &Profile,
+    sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    let sparseness = sketch.novelty_archive.assess(Behaviour::from(profile));
+    fitness.insert("novelty", 1.0 / (1.0 + sparseness));
+}
+
+/// How much of the memory pattern is missing, and whether what's there
+/// is word-aligned.
+pub fn string_error(
+    profile: &Profile,
+    _sketch: &mut Sketches,
+    config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    if let Some(pattern) = config.roper.memory_pattern() {
+        let word_size = get_static_memory_image().word_size;
+        let mut string_errors = vec![];
+        let mut misalignments = vec![];
+        for (write_log, memory) in profile.write_logs.iter().zip(profile.writeable_memory.iter())
+        {
+            let found = pattern.longest_match(write_log, memory, word_size);
+            let len = found.map(|m| m.len).unwrap_or(0);
+            string_errors.push((pattern.bytes.len() - len) as f64);
+            misalignments.push(match found {
+                Some(m) if m.is_aligned(word_size) => 0.0,
+                _ => 1.0,
+            });
+        }
+        fitness.insert("string_error", stats::mean(string_errors.into_iter()));
+        fitness.insert(
+            "string_misalignment",
+            stats::mean(misalignments.into_iter()),
+        );
+    } else {
+        log::error!("No memory pattern?");
+    }
+}
+
+/// How far the memory pattern's register is from pointing at the bytes
+/// written. Only recorded if the pattern names a register.
+pub fn pointer_error(
+    profile: &Profile,
+    _sketch: &mut Sketches,
+    config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    let pattern = match config.roper.memory_pattern() {
+        Some(pattern) => pattern,
+        None => {
+            log::error!("No memory pattern?");
+            return;
+        }
+    };
+    let reg = match pattern.register {
+        Some(ref reg) => reg,
+        None => return,
+    };
+    let word_size = get_static_memory_image().word_size;
+    let max_pointer_error = (word_size * 8) as f64;
+    let pointer_errors = profile
+        .write_logs
+        .iter()
+        .zip(profile.writeable_memory.iter())
+        .zip(profile.registers.iter())
+        .map(|((write_log, memory), registers)| {
+            // In staged mode, the pointer only counts once the string's in place
+            match pattern.longest_match(write_log, memory, word_size) {
+                Some(m) if !pattern.staged || m.len == pattern.bytes.len() => registers
+                    .0
+                    .get(reg)
+                    .map(|vals| (vals[0] ^ m.addr).count_ones() as f64)
+                    .unwrap_or(max_pointer_error),
+                _ => max_pointer_error,
+            }
+        });
+    fitness.insert("pointer_error", stats::mean(pointer_errors));
+}
+
+pub fn mem_write_novelty(
+    profile: &Profile,
+    sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    let mem_scores = profile
+        .write_logs
+        .iter()
+        .flatten()
+        .map(|m| {
+            sketch.memory_writes.insert(m);
+            sketch.memory_writes.query(m)
+        })
+        .collect::<Vec<f64>>();
+    let mem_write_novelty = if mem_scores.is_empty() {
+        1.0
+    } else {
+        stats::mean(mem_scores.into_iter())
+    };
+    fitness.insert("mem_write_novelty", mem_write_novelty);
+}
+
+pub fn mem_write_ratio(
+    profile: &Profile,
+    _sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    fitness.insert("mem_write_ratio", profile.mem_write_ratio());
+}
+
+pub fn crash_count(
+    profile: &Profile,
+    _sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    let crashes = profile.cpu_errors.values().sum::<usize>() as f64;
+    fitness.insert("crash_count", crashes);
+}
+
+pub fn gadgets_executed(
+    profile: &Profile,
+    _sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    fitness.insert("gadgets_executed", profile.gadgets_executed.len() as f64);
+}
+
+/// Records the fraction of executable memory left unvisited, as
+/// `code_coverage`, and the mean frequency of the addresses visited, as
+/// `code_frequency`.
+pub fn code_coverage(
+    profile: &Profile,
+    sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    let mut addresses_visited = HashSet::new();
+    // TODO: optimize this, maybe parallelize
+    profile.basic_block_path_iterator().for_each(|path| {
+        for block in path {
+            for addr in block.entry..(block.entry + block.size as u64) {
+                addresses_visited.insert(addr);
+            }
+        }
+    });
+    let mut freq_score = 0.0;
+    for addr in addresses_visited.iter() {
+        sketch.addresses_visited.insert(*addr);
+        freq_score += sketch.addresses_visited.query(*addr);
+    }
+    let num_addr_visit = addresses_visited.len() as f64;
+    let avg_freq = if num_addr_visit < 1.0 {
+        1.0
+    } else {
+        freq_score / num_addr_visit
+    };
+    // might be worth memoizing this call, but it's pretty cheap
+    let code_size = get_static_memory_image().size_of_executable_memory();
+    let code_coverage = 1.0 - num_addr_visit / code_size as f64;
+
+    fitness.insert("code_coverage", code_coverage);
+    fitness.insert("code_frequency", avg_freq);
+}
+
+/// The entropy of the final register values, as `register_entropy`.
+pub fn entropy(
+    profile: &Profile,
+    _sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    if let Some(registers) = profile.registers.last() {
+        let just_regs = registers.0.values().map(|v| v[0]).collect::<Vec<u64>>();
+        fitness.insert("register_entropy", just_regs.entropy());
+    }
+}
+
+/// The number of zero bits in the conjunction of the final register values.
+pub fn zeroes(
+    profile: &Profile,
+    _sketch: &mut Sketches,
+    _config: &Config,
+    fitness: &mut Weighted<'static>,
+) {
+    if let Some(registers) = profile.registers.last() {
+        let word_size = get_static_memory_image().word_size * 8;
+        let mut conj = registers.0.values().fold(!0_u64, |a, b| a & b[0]);
+        let mask = match word_size {
+            64 => 0x0000_0000_0000_0000,
+            32 => 0xFFFF_FFFF_0000_0000,
+            16 => 0xFFFF_FFFF_FFFF_0000,
+            _ => unreachable!("not a size"),
+        };
+        conj |= mask;
+        let score = conj.count_zeros() as f64;
+        // ignore bits outside of the register's word size
+        debug_assert!(score <= word_size as f64);
+        fitness.insert("zeroes", score);
+    }
+}
@@ -2,27 +2,34 @@ use std::fs::File;
 use std::hash::Hash;
 use std::hash::Hasher;
 use std::io::{BufRead, BufReader};
+use std::iter;
 use std::sync::Arc;
 use std::thread::spawn;
 
-use non_dominated_sort::DominanceOrd;
 use rand::Rng;
 use unicorn::Cpu;
 
 use crate::configure::{Config, Selection};
+use crate::emulator::profiler::HasProfile;
+use crate::emulator::register_pattern::RegisterCase;
 use crate::error::Error;
+use crate::evolution::alps::Alps;
+use crate::evolution::lexicase::Lexicase;
+use crate::evolution::map_elites::MapElites;
 use crate::evolution::metropolis::Metropolis;
 use crate::evolution::pareto_roulette::Roulette;
 use crate::evolution::population::pier::Pier;
 use crate::fitness::Weighted;
 use crate::observer::Observer;
-use crate::ontogenesis::FitnessFn;
+use crate::ontogenesis::{Develop, FitnessFn};
+use crate::roper::descriptor::Descriptor;
+use crate::roper::novelty::NoveltyArchive;
 use crate::util::architecture::Perms;
 use crate::util::count_min_sketch::CountMinSketch;
 use crate::util::random::hash_seed_rng;
 use crate::{
     emulator::loader,
-    evolution::{tournament::Tournament, Phenome},
+    evolution::{tournament::Tournament, Genome, Phenome, World},
 };
 
 /// The `analysis` module contains the reporting function passed to the observation
@@ -33,6 +40,16 @@ mod analysis;
 /// mode ROPER.
 mod fitness_functions;
 
+/// The named fitness components from which fitness functions can be
+/// assembled, either in code or in the config.
+mod fitness_components;
+
+/// The behavioural features that divide the MAP-Elites archive.
+mod descriptor;
+
+/// Behavioural novelty search, measured against an archive of behaviours.
+mod novelty;
+
 /// The `creature` module contains the implementation of the `Genome` and `Phenome`
 /// traits associated with `roper` mode.
 mod bare;
@@ -46,7 +63,11 @@ pub fn init_soup(config: &mut Config) -> Result<(), Error> {
     let mut soup = Vec::new();
     //might as well take the constants from the register pattern
     if let Some(pattern) = config.roper.register_pattern() {
-        pattern.0.values().for_each(|w| soup.push(w.val))
+        pattern
+            .0
+            .values()
+            .filter_map(|w| w.val.leading_word())
+            .for_each(|w| soup.push(w))
     }
     if let Some(gadget_file) = config.roper.gadget_file.as_ref() {
         // parse the gadget file
@@ -82,6 +103,7 @@ pub struct Sketches {
     pub register_error: CountMinSketch,
     pub memory_writes: CountMinSketch,
     pub addresses_visited: CountMinSketch,
+    pub novelty_archive: NoveltyArchive,
 }
 
 impl Sketches {
@@ -90,68 +112,110 @@ impl Sketches {
             register_error: CountMinSketch::new(config),
             memory_writes: CountMinSketch::new(config),
             addresses_visited: CountMinSketch::new(config),
+            novelty_archive: NoveltyArchive::new(config),
         }
     }
 }
 
 type Fitness<'a> = Weighted<'a>; //Pareto<'static>;
 
-fn prepare_bare<C: 'static + Cpu<'static>>(
-    config: &Config,
-) -> (Observer<bare::Creature>, bare::evaluation::Evaluator<C>) {
-    let fitness_function: FitnessFn<bare::Creature, Sketches, Config> =
-        match config.fitness.function.as_str() {
-            "register_pattern" => Box::new(fitness_functions::register_pattern_ff),
-            "register_conjunction" => Box::new(fitness_functions::register_conjunction_ff),
-            "register_entropy" => Box::new(fitness_functions::register_entropy_ff),
-            "code_coverage" => Box::new(fitness_functions::code_coverage_ff),
-            "just_novelty" => Box::new(fitness_functions::just_novelty_ff),
-            s => unimplemented!("No such fitness function as {}", s),
-        };
-    let observer = Observer::spawn(&config, Box::new(analysis::report_fn));
-    let evaluator = bare::evaluation::Evaluator::spawn(&config, fitness_function);
-    (observer, evaluator)
+fn fitness_function<P>(name: &str, components: &[String]) -> FitnessFn<P, Sketches, Config>
+where
+    P: 'static + HasProfile + Phenome<Fitness = Fitness<'static>> + Sized,
+{
+    match name {
+        "components" => fitness_components::components_fitness_function(components),
+        "register_pattern" => Box::new(fitness_functions::register_pattern_ff),
+        "memory_pattern" => Box::new(fitness_functions::memory_pattern_ff),
+        "register_conjunction" => Box::new(fitness_functions::register_conjunction_ff),
+        "register_entropy" => Box::new(fitness_functions::register_entropy_ff),
+        "code_coverage" => Box::new(fitness_functions::code_coverage_ff),
+        "just_novelty" => Box::new(fitness_functions::just_novelty_ff),
+        "novelty_search" => Box::new(fitness_functions::novelty_search_ff),
+        s => unimplemented!("No such fitness function as {}", s),
+    }
 }
 
-fn prepare_push<C: 'static + Cpu<'static>>(
-    config: &Config,
-) -> (Observer<push::Creature>, push::evaluation::Evaluator<C>) {
-    let fitness_function: FitnessFn<push::Creature, Sketches, Config> =
-        match config.fitness.function.as_str() {
-            "register_pattern" => Box::new(fitness_functions::register_pattern_ff),
-            "register_conjunction" => Box::new(fitness_functions::register_conjunction_ff),
-            "register_entropy" => Box::new(fitness_functions::register_entropy_ff),
-            "code_coverage" => Box::new(fitness_functions::code_coverage_ff),
-            "just_novelty" => Box::new(fitness_functions::just_novelty_ff),
-            s => unimplemented!("No such fitness function as {}", s),
-        };
-    let observer: Observer<push::Creature> =
-        Observer::spawn(&config, Box::new(analysis::report_fn));
-    let evaluator = push::evaluation::Evaluator::spawn(&config, fitness_function);
-    (observer, evaluator)
+/// When stages are configured, this dispatches to the fitness function of
+/// whichever stage the island is on, and records that stage in the fitness,
+/// so that the observer can tell which stage a creature was scored under.
+fn staged_fitness_function<P>(config: &Config) -> FitnessFn<P, Sketches, Config>
+where
+    P: 'static + HasProfile + Phenome<Fitness = Fitness<'static>> + Sized,
+{
+    if config.fitness.stages.is_empty() {
+        return fitness_function(&config.fitness.function, &config.fitness.components);
+    }
+    let functions = config
+        .fitness
+        .stages
+        .iter()
+        .map(|stage| (stage.function.as_str(), stage.components.as_slice()))
+        .chain(std::iter::once((
+            config.fitness.function.as_str(),
+            config.fitness.components.as_slice(),
+        )))
+        .map(|(name, components)| fitness_function(name, components))
+        .collect::<Vec<FitnessFn<P, Sketches, Config>>>();
+    Box::new(move |creature: P, sketches: &mut Sketches, config: Arc<Config>| {
+        let stage = config.fitness.stage();
+        let mut creature = (functions[stage])(creature, sketches, config);
+        if let Some(mut fitness) = creature.fitness().cloned() {
+            fitness.set_stage(stage);
+            creature.set_fitness(fitness);
+        }
+        creature
+    })
+}
+
+/// What the launcher needs to know about a kind of ROPER creature, so
+/// that any of them can be evolved under any selection scheme.
+trait RoperCreature<C: 'static + Cpu<'static>>:
+    'static + Genome + HasProfile + Phenome<Fitness = Fitness<'static>, Problem = RegisterCase> + Sized
+{
+    type Evaluator: Develop<Self> + Send + 'static;
+
+    fn spawn_evaluator(
+        config: &Config,
+        fitness_fn: FitnessFn<Self, Sketches, Config>,
+    ) -> Self::Evaluator;
 }
 
-pub struct CreatureDominanceOrd;
+impl<C: 'static + Cpu<'static>> RoperCreature<C> for bare::Creature {
+    type Evaluator = bare::evaluation::Evaluator<C>;
 
-impl DominanceOrd<bare::Creature> for CreatureDominanceOrd {
-    fn dominance_ord(&self, a: &bare::Creature, b: &bare::Creature) -> std::cmp::Ordering {
-        a.fitness()
-            .partial_cmp(&b.fitness())
-            .unwrap_or(std::cmp::Ordering::Equal)
+    fn spawn_evaluator(
+        config: &Config,
+        fitness_fn: FitnessFn<Self, Sketches, Config>,
+    ) -> Self::Evaluator {
+        bare::evaluation::Evaluator::spawn(config, fitness_fn)
     }
 }
 
-impl DominanceOrd<push::Creature> for CreatureDominanceOrd {
-    fn dominance_ord(&self, a: &push::Creature, b: &push::Creature) -> std::cmp::Ordering {
-        a.fitness()
-            .partial_cmp(&b.fitness())
-            .unwrap_or(std::cmp::Ordering::Equal)
+impl<C: 'static + Cpu<'static>> RoperCreature<C> for push::Creature {
+    type Evaluator = push::evaluation::Evaluator<C>;
+
+    fn spawn_evaluator(
+        config: &Config,
+        fitness_fn: FitnessFn<Self, Sketches, Config>,
+    ) -> Self::Evaluator {
+        push::evaluation::Evaluator::spawn(config, fitness_fn)
     }
 }
 
-impl DominanceOrd<&bare::Creature> for CreatureDominanceOrd {}
+fn prepare<C, P>(config: &Config) -> (Observer<P>, P::Evaluator)
+where
+    C: 'static + Cpu<'static>,
+    P: RoperCreature<C>,
+{
+    let observer = Observer::spawn(config, Box::new(analysis::report_fn));
+    let evaluator = P::spawn_evaluator(config, staged_fitness_function(config));
+    (observer, evaluator)
+}
 
-impl DominanceOrd<&push::Creature> for CreatureDominanceOrd {}
+/// This works for both bare and push creatures, and respects whichever
+/// fitness representation has been chosen in the config.
+pub use crate::evolution::FitnessDominanceOrd as CreatureDominanceOrd;
 
 pub fn run(mut config: Config) {
     let _ = loader::falcon_loader::load_from_path(&mut config.roper, true)
@@ -171,121 +235,99 @@ pub fn run(mut config: Config) {
 }
 
 pub fn launch<C: 'static + Cpu<'static>>(config: Config) {
+    if config.roper.use_push {
+        launch_with::<C, push::Creature>(config)
+    } else {
+        launch_with::<C, bare::Creature>(config)
+    }
+}
+
+fn launch_with<C, P>(config: Config)
+where
+    C: 'static + Cpu<'static>,
+    P: RoperCreature<C>,
+{
     match config.selection {
-        Selection::Tournament => {
-            // TODO: Refactor this!!
-            let num_islands = config.num_islands;
-            if config.roper.use_push {
-                let pier: Arc<Pier<push::Creature>> = Arc::new(Pier::new(config.num_islands));
-                let mut handles = Vec::new();
-                let mut rng = hash_seed_rng(&config.random_seed);
-                for i in 0..num_islands {
-                    let mut config = config.clone();
-                    config.island_identifier = i;
-                    config.set_data_directory();
-                    config.random_seed = rng.gen::<u64>();
-                    let (observer, evaluator) = prepare_push(&config);
-                    let pier = pier.clone();
-                    let h = spawn(move || {
-                        let mut world =
-                            Tournament::<push::evaluation::Evaluator<C>, push::Creature>::new(
-                                &config, observer, evaluator, pier,
-                            );
-                        while crate::keep_going() {
-                            world = world.evolve();
-                        }
-                    });
-                    handles.push(h);
-                }
-                for h in handles.into_iter() {
-                    h.join().expect("Failed to join thread");
-                }
-            } else {
-                let pier: Arc<Pier<bare::Creature>> = Arc::new(Pier::new(config.num_islands));
-                let mut handles = Vec::new();
-                let mut rng = hash_seed_rng(&config.random_seed);
-                for i in 0..num_islands {
-                    let mut config = config.clone();
-                    config.island_identifier = i;
-                    config.set_data_directory();
-                    config.random_seed = rng.gen::<u64>();
-                    let (observer, evaluator) = prepare_bare(&config);
-                    let pier = pier.clone();
-                    let h = spawn(move || {
-                        let mut world =
-                            Tournament::<bare::evaluation::Evaluator<C>, bare::Creature>::new(
-                                &config, observer, evaluator, pier,
-                            );
-                        while crate::keep_going() {
-                            world = world.evolve();
-                        }
-                    });
-                    handles.push(h);
-                }
-                for h in handles.into_iter() {
-                    h.join().expect("Failed to join thread");
-                }
-            }
-        }
+        Selection::Tournament => run_islands::<C, P, _, _>(&config, Tournament::new),
+        Selection::Alps => run_islands::<C, P, _, _>(&config, Alps::new),
         Selection::Roulette => {
-            let (observer, evaluator) = prepare_bare(&config);
-            let mut world = Roulette::<
-                bare::evaluation::Evaluator<C>,
-                bare::Creature,
-                CreatureDominanceOrd,
-            >::new(&config, observer, evaluator, CreatureDominanceOrd);
-            while crate::keep_going() {
-                world = world.evolve();
-            }
+            run_islands::<C, P, _, _>(&config, |config, observer, evaluator, _pier| {
+                Roulette::new(config, observer, evaluator, CreatureDominanceOrd)
+            })
         }
         Selection::Metropolis => {
-            let (observer, evaluator) = prepare_bare(&config);
-            let mut world = Metropolis::<bare::evaluation::Evaluator<C>, bare::Creature>::new(
-                &config, observer, evaluator,
-            );
-            while crate::keep_going() {
-                world = world.evolve();
-            }
+            run_islands::<C, P, _, _>(&config, |config, observer, evaluator, _pier| {
+                // each replica evaluates its specimens with its own evaluator
+                let evaluators = iter::once(evaluator)
+                    .chain(
+                        (1..config.metropolis.replicas.max(1))
+                            .map(|_| P::spawn_evaluator(config, staged_fitness_function(config))),
+                    )
+                    .collect::<Vec<_>>();
+                Metropolis::new(config, observer, evaluators)
+            })
         }
-        Selection::Lexicase => unimplemented!("Probably needs an overhaul"),
-        // Selection::Lexicase => {
-        //     let fitness_function: FitnessFn<bare::Creature, Sketches, Config> =
-        //         match config.fitness.function.as_str() {
-        //             "register_pattern" => Box::new(bare::evaluation::register_pattern_ff),
-        //             "register_conjunction" => Box::new(bare::evaluation::register_conjunction_ff),
-        //             "code_coverage" => Box::new(bare::evaluation::code_coverage_ff),
-        //             s => unimplemented!("No such fitness function as {}", s),
-        //         };
-        //     let pier: Arc<Pier<bare::Creature>> = Arc::new(Pier::new(config.num_islands));
-        //     let evaluator = bare::evaluation::BareEvaluator::spawn(&config, fitness_function);
-        //     let observer = Observer::spawn(
-        //         &config,
-        //         Box::new(bare::analysis::lexicase::report_fn),
-        //         CreatureDominanceOrd,
-        //     );
-        //     let mut cases = config
-        //         .roper
-        //         .register_pattern
-        //         .as_ref()
-        //         .map(|rp| {
-        //             let pattern: RegisterPattern = rp.into();
-        //             pattern
-        //                 .features()
-        //                 .into_iter()
-        //                 .map(lexi::Task::Reg)
-        //                 .collect::<Vec<lexi::Task>>()
-        //         })
-        //         .expect("No register pattern specified");
-        //     cases.push(lexi::Task::UniqExec(2));
-        //     cases.push(lexi::Task::UniqExec(3));
-        //     log::info!("Register Feature cases: {:#x?}", cases);
-        //     let mut world =
-        //         Lexicase::<lexi::Task, bare::evaluation::BareEvaluator<C>, bare::Creature>::new(
-        //             &config, observer, evaluator, pier, cases,
-        //         );
-        //     while crate::keep_going() {
-        //         world = world.evolve();
-        //     }
-        // }
+        Selection::Lexicase => {
+            let cases = {
+                let mut config = config.clone();
+                config.roper.parse_register_pattern();
+                config
+                    .roper
+                    .register_pattern()
+                    .map(|pattern| pattern.cases(config.roper.num_inputs))
+                    .expect("Lexicase selection requires a register pattern")
+            };
+            log::info!("Register feature cases: {:#x?}", cases);
+            run_islands::<C, P, _, _>(&config, move |config, observer, evaluator, pier| {
+                Lexicase::new(config, observer, evaluator, pier, cases.clone())
+            })
+        }
+        Selection::MapElites => {
+            let descriptor = Descriptor::new(&config);
+            run_islands::<C, P, _, _>(&config, move |config, observer, evaluator, _pier| {
+                let descriptor = descriptor.clone();
+                MapElites::new(
+                    config,
+                    observer,
+                    evaluator,
+                    Box::new(move |creature: &P| {
+                        creature
+                            .profile()
+                            .map(|profile| descriptor.describe(profile))
+                    }),
+                )
+            })
+        }
+    }
+}
+
+/// Runs a world, built by `build`, on each of `num_islands` threads. The
+/// islands share a pier, and each has its own identifier, data directory,
+/// random seed, observer and evaluator.
+fn run_islands<C, P, W, F>(config: &Config, build: F)
+where
+    C: 'static + Cpu<'static>,
+    P: RoperCreature<C>,
+    W: World,
+    F: Fn(&Config, Observer<P>, P::Evaluator, Arc<Pier<P>>) -> W + Send + Sync + 'static,
+{
+    let build = Arc::new(build);
+    let pier: Arc<Pier<P>> = Arc::new(Pier::new(config));
+    let mut rng = hash_seed_rng(&config.random_seed);
+    let mut handles = Vec::new();
+    for i in 0..config.num_islands {
+        let mut config = config.clone();
+        config.island_identifier = i;
+        config.fitness.reset_stage();
+        config.set_data_directory();
+        config.random_seed = rng.gen::<u64>();
+        let (observer, evaluator) = prepare::<C, P>(&config);
+        let pier = pier.clone();
+        let build = build.clone();
+        let h = spawn(move || build(&config, observer, evaluator, pier).run());
+        handles.push(h);
+    }
+    for h in handles.into_iter() {
+        h.join().expect("Failed to join thread");
     }
 }
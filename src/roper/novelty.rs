@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::configure::Config;
+use crate::emulator::profiler::Profile;
+use crate::util::bitwise::ham_rat;
+use crate::util::dump::dump;
+
+/// The behaviour descriptor used by novelty search: the final register
+/// values, the addresses written to, and the entry points of the basic
+/// blocks visited, on every input.
+#[derive(Clone, Debug, Serialize)]
+pub struct Behaviour {
+    registers: Vec<u64>,
+    writes: Vec<u64>,
+    blocks: Vec<u64>,
+}
+
+impl From<&Profile> for Behaviour {
+    fn from(profile: &Profile) -> Self {
+        let registers = profile
+            .registers
+            .iter()
+            .flat_map(|state| {
+                state
+                    .0
+                    .iter()
+                    .sorted_by_key(|p| p.0)
+                    .map(|(_, vals)| vals[0])
+                    .collect::<Vec<u64>>()
+            })
+            .collect();
+        let writes = profile
+            .addresses_written_to()
+            .into_iter()
+            .sorted()
+            .collect();
+        let blocks = profile
+            .basic_block_path_iterator()
+            .flatten()
+            .map(|b| b.entry)
+            .sorted()
+            .dedup()
+            .collect();
+        Self {
+            registers,
+            writes,
+            blocks,
+        }
+    }
+}
+
+/// The Jaccard distance between two sorted, deduplicated sets.
+fn jaccard_distance(a: &[u64], b: &[u64]) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 0.0;
+    }
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            shared += 1;
+            i += 1;
+            j += 1;
+        } else if a[i] < b[j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    let union = a.len() + b.len() - shared;
+    1.0 - shared as f64 / union as f64
+}
+
+impl Behaviour {
+    /// A distance between 0.0 and 1.0, giving equal weight to the
+    /// registers, the writes, and the blocks visited.
+    pub fn distance(&self, other: &Self) -> f64 {
+        let len = std::cmp::max(self.registers.len(), other.registers.len());
+        let register_distance = if len == 0 {
+            0.0
+        } else {
+            (0..len)
+                .map(|i| match (self.registers.get(i), other.registers.get(i)) {
+                    (Some(a), Some(b)) => ham_rat(*a, *b),
+                    _ => 1.0,
+                })
+                .sum::<f64>()
+                / len as f64
+        };
+        (register_distance
+            + jaccard_distance(&self.writes, &other.writes)
+            + jaccard_distance(&self.blocks, &other.blocks))
+            / 3.0
+    }
+}
+
+/// The archive of novel behaviours, against which each newly evaluated
+/// creature's novelty is measured, along with the behaviours most recently
+/// evaluated, which stand in for the current population.
+pub struct NoveltyArchive {
+    archive: VecDeque<Behaviour>,
+    recent: VecDeque<Behaviour>,
+    k: usize,
+    threshold: f64,
+    capacity: usize,
+    window: usize,
+    dump_every: usize,
+    additions: usize,
+    data_directory: String,
+}
+
+impl NoveltyArchive {
+    pub fn new(config: &Config) -> Self {
+        let novelty = &config.novelty;
+        Self {
+            archive: VecDeque::new(),
+            recent: VecDeque::new(),
+            k: novelty.k,
+            threshold: novelty.threshold,
+            capacity: novelty.capacity,
+            window: novelty.window,
+            dump_every: novelty.dump_every,
+            additions: 0,
+            data_directory: config.data_directory().to_string(),
+        }
+    }
+
+    /// The mean distance from the behaviour to its `k` nearest neighbours
+    /// in the archive and among the recently evaluated. If there's nothing
+    /// to compare it to yet, it's considered maximally sparse.
+    pub fn sparseness(&self, behaviour: &Behaviour) -> f64 {
+        let mut distances = self
+            .archive
+            .iter()
+            .chain(self.recent.iter())
+            .map(|other| behaviour.distance(other))
+            .collect::<Vec<f64>>();
+        if distances.is_empty() {
+            return 1.0;
+        }
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let k = std::cmp::min(self.k, distances.len());
+        distances[..k].iter().sum::<f64>() / k as f64
+    }
+
+    /// Measures the behaviour's sparseness, and then remembers it, adding it
+    /// to the archive if it's sparse enough.
+    pub fn assess(&mut self, behaviour: Behaviour) -> f64 {
+        let sparseness = self.sparseness(&behaviour);
+        if sparseness >= self.threshold {
+            self.archive.push_back(behaviour.clone());
+            if self.archive.len() > self.capacity {
+                let _ = self.archive.pop_front();
+            }
+            self.additions += 1;
+            if self.dump_every > 0 && self.additions % self.dump_every == 0 {
+                self.dump();
+            }
+        }
+        self.recent.push_back(behaviour);
+        if self.recent.len() > self.window {
+            let _ = self.recent.pop_front();
+        }
+        sparseness
+    }
+
+    pub fn dump(&self) {
+        let path = format!(
+            "{}/novelty/archive_{}.json.gz",
+            self.data_directory, self.additions
+        );
+        log::info!(
+            "Dumping novelty archive of {} behaviours to {}",
+            self.archive.len(),
+            path
+        );
+        if let Err(e) = dump(&self.archive, &path) {
+            log::error!("Failed to dump novelty archive: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_behaviour_distance() {
+        let a = Behaviour {
+            registers: vec![0, 0],
+            writes: vec![1, 2, 3],
+            blocks: vec![0x1000],
+        };
+        let b = Behaviour {
+            registers: vec![0, !0],
+            writes: vec![2, 3, 4],
+            blocks: vec![0x1000],
+        };
+        assert!(a.distance(&a) < std::f64::EPSILON);
+        // half the registers differ entirely, and the writes share 2 of 4
+        let expected = (0.5 + 0.5 + 0.0) / 3.0;
+        assert!((a.distance(&b) - expected).abs() < std::f64::EPSILON);
+        assert!((a.distance(&b) - b.distance(&a)).abs() < std::f64::EPSILON);
+    }
+}
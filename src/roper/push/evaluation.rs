@@ -1,13 +1,13 @@
-use std::convert::TryInto;
 use std::sync::Arc;
 
+use hashbrown::HashMap;
 use unicorn::Cpu;
 
 use crate::configure::Config;
 use crate::emulator::hatchery::Hatchery;
-use crate::emulator::profiler::{HasProfile, Profile};
-use crate::emulator::register_pattern::{Register, UnicornRegisterState};
-use crate::evolution::{Genome, Phenome};
+use crate::emulator::profiler::Profile;
+use crate::emulator::register_pattern::Register;
+use crate::evolution::{GeneRole, Genome, Phenome};
 use crate::fitness::Weighted;
 use crate::ontogenesis::{Develop, FitnessFn};
 use crate::roper::push;
@@ -26,6 +26,7 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
     pub fn spawn(config: &Config, fitness_fn: FitnessFn<Creature, Sketches, Config>) -> Self {
         let mut config = config.clone();
         config.roper.parse_register_pattern();
+        config.roper.parse_memory_pattern();
         let hatch_config = Arc::new(config.roper.clone());
         let register_pattern = config.roper.register_pattern();
         let output_registers: Vec<Register<C>> = {
@@ -36,22 +37,41 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
                 .map(|s| s.parse().ok().expect("Failed to parse output register"))
                 .collect::<Vec<_>>();
             if let Some(pat) = register_pattern {
-                let arch_specific_pat: UnicornRegisterState<C> =
-                    pat.try_into().expect("Failed to parse register pattern");
-                let regs_in_pat = arch_specific_pat.0.keys().cloned().collect::<Vec<_>>();
-                out_reg.extend_from_slice(&regs_in_pat);
-                out_reg.dedup();
-                out_reg
-            } else {
-                out_reg
-                //todo!("implement a conversion method from problem sets to register maps");
+                // this includes any registers that the pattern's targets refer to
+                for r in pat.registers() {
+                    let reg: Register<C> = r
+                        .parse()
+                        .ok()
+                        .expect("Failed to parse register in pattern");
+                    if !out_reg.contains(&reg) {
+                        out_reg.push(reg);
+                    }
+                }
+            }
+            if let Some(r) = config
+                .roper
+                .memory_pattern()
+                .and_then(|pat| pat.register.as_ref())
+            {
+                let reg: Register<C> = r
+                    .parse()
+                    .ok()
+                    .expect("Failed to parse register in memory pattern");
+                if !out_reg.contains(&reg) {
+                    out_reg.push(reg);
+                }
             }
+            out_reg
         };
         let inputs = if config.roper.randomize_registers {
-            vec![util::architecture::random_register_state::<u64, C>(
-                &output_registers,
-                config.random_seed,
-            )]
+            (0..config.roper.num_inputs)
+                .map(|i| {
+                    util::architecture::random_register_state::<u64, C>(
+                        &output_registers,
+                        config.random_seed ^ i as u64,
+                    )
+                })
+                .collect::<Vec<_>>()
         } else {
             vec![util::architecture::constant_register_state::<C>(
                 &output_registers,
@@ -118,10 +138,41 @@ impl<C: 'static + Cpu<'static>> Develop<push::Creature> for Evaluator<C> {
 
     fn apply_fitness_function(&mut self, mut creature: push::Creature) -> push::Creature {
         let profile = creature
-            .profile()
+            .profile
+            .as_ref()
             .expect("Attempted to apply fitness function to undeveloped creature");
-        if !profile.executable {
-            let mut fitness = Weighted::new(&self.config.fitness.weighting);
+        let executable = profile.executable;
+        // A gene counts as executed if it's a constant word that was
+        // executed as a gadget.
+        let executed = &profile.gadgets_executed;
+        creature.chromosome.refresh_genes(|op| match op {
+            push::Op::WordConst(w) => executed.contains(w),
+            _ => false,
+        });
+        // A constant word takes on the most significant role it played
+        // anywhere in the payload. Other ops play no part of their own.
+        let mut word_roles = HashMap::new();
+        if let Some(payload) = creature.payload.as_ref() {
+            for (word, role) in payload.iter().zip(profile.word_roles(payload)) {
+                let strongest = word_roles.entry(*word).or_insert(role);
+                if role_rank(role) > role_rank(*strongest) {
+                    *strongest = role;
+                }
+            }
+        }
+        creature.chromosome.roles = creature
+            .chromosome
+            .chromosome
+            .iter()
+            .map(|op| match op {
+                push::Op::WordConst(w) => {
+                    word_roles.get(w).copied().unwrap_or(GeneRole::Unexecuted)
+                }
+                _ => GeneRole::Unknown,
+            })
+            .collect();
+        if !executable {
+            let mut fitness = Weighted::from_config(&self.config.fitness);
             fitness.declare_failure();
             creature.set_fitness(fitness);
             creature
@@ -140,3 +191,15 @@ impl<C: 'static + Cpu<'static>> Develop<push::Creature> for Evaluator<C> {
             .collect::<Vec<push::Creature>>()
     }
 }
+
+/// How much a role says about the part a word played: having been
+/// executed anywhere in the payload outweighs having been consumed, which
+/// outweighs having been passed over.
+fn role_rank(role: GeneRole) -> usize {
+    match role {
+        GeneRole::Unknown => 0,
+        GeneRole::Unexecuted => 1,
+        GeneRole::Consumed => 2,
+        GeneRole::Executed => 3,
+    }
+}
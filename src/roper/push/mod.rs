@@ -1,7 +1,7 @@
 use std::fmt;
 
 use falcon::il;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use rand::prelude::SliceRandom;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -1279,15 +1279,16 @@ pub mod creature {
     use std::hash::{Hash, Hasher};
 
     use byteorder::{BigEndian, ByteOrder, LittleEndian};
-    use rand::thread_rng;
 
+    use crate::configure::Distance;
     use crate::emulator::pack::Pack;
     use crate::emulator::profiler::{HasProfile, Profile};
-    use crate::evolution::{Genome, LinearChromosome, Mutation, Phenome};
+    use crate::emulator::register_pattern::RegisterCase;
+    use crate::evolution::{GeneRole, Genome, LinearChromosome, Mutation, Phenome};
     use crate::roper::Fitness;
     use crate::util;
     use crate::util::architecture::Endian;
-    use crate::util::random::hash_seed_rng;
+    use crate::util::random::{hash_seed_rng, Prng};
 
     use super::*;
 
@@ -1301,9 +1302,8 @@ pub mod creature {
 
         // TODO: should we have a pointer to the config here?
         // we might want to take certain parameters into consideration, like the literal rate
-        fn mutate_point(allele: &mut Self::Allele, config: &Config) -> Self {
-            let mut rng = thread_rng();
-            let new_allele = random_ops(&mut rng, config).pop().unwrap();
+        fn mutate_point(allele: &mut Self::Allele, config: &Config, rng: &mut Prng) -> Self {
+            let new_allele = random_ops(rng, config).pop().unwrap();
             *allele = new_allele;
             OpMutation::RandomOp
         }
@@ -1422,7 +1422,7 @@ pub mod creature {
             Self: Sized,
         {
             // First, let's get some information from the lifted program
-            let mut rng = hash_seed_rng(&salt);
+            let mut rng = hash_seed_rng(&(salt, config.random_seed));
             let length = rng.gen_range(config.push_vm.min_len, config.push_vm.max_len);
             let ops = random_ops(&mut rng, config);
 
@@ -1430,6 +1430,8 @@ pub mod creature {
                 chromosome: LinearChromosome {
                     chromosome: ops,
                     mutations: vec![None; length],
+                    ages: vec![0; length],
+                    roles: vec![GeneRole::Unknown; length],
                     parentage: vec![],
                     parent_names: vec![],
                     name: util::name::random(4, rng.gen::<u64>()),
@@ -1446,7 +1448,32 @@ pub mod creature {
             }
         }
 
-        fn crossover(mates: &[&Self], config: &Config) -> Self
+        fn from_chromosome<H: Hash>(chromosome: Vec<Op>, config: &Config, salt: H) -> Option<Self> {
+            let len = chromosome.len();
+            let mut rng = hash_seed_rng(&(salt, config.random_seed));
+            Some(Self {
+                chromosome: LinearChromosome {
+                    chromosome,
+                    mutations: vec![None; len],
+                    ages: vec![0; len],
+                    roles: vec![GeneRole::Unknown; len],
+                    parentage: vec![],
+                    parent_names: vec![],
+                    name: util::name::random(4, rng.gen::<u64>()),
+                    generation: 0,
+                },
+                tag: rng.gen::<u64>(),
+                payload: None,
+                profile: None,
+                fitness: None,
+                front: None,
+                num_offspring: 0,
+                native_island: config.island_identifier,
+                description: None,
+            })
+        }
+
+        fn crossover(mates: &[&Self], config: &Config, rng: &mut Prng) -> Self
         where
             Self: Sized,
         {
@@ -1454,10 +1481,10 @@ pub mod creature {
                 .iter()
                 .map(|x| &x.chromosome)
                 .collect::<Vec<&LinearChromosome<_, _>>>();
-            let chromosome = LinearChromosome::crossover(&parents, config);
+            let chromosome = LinearChromosome::crossover(&parents, config, rng);
             Self {
                 chromosome,
-                tag: thread_rng().gen::<u64>(),
+                tag: rng.gen::<u64>(),
                 payload: None,
                 profile: None,
                 fitness: None,
@@ -1468,18 +1495,30 @@ pub mod creature {
             }
         }
 
-        fn mutate(&mut self, config: &Config) {
-            self.chromosome.mutate(config)
+        fn mutate(&mut self, config: &Config, rng: &mut Prng) {
+            self.chromosome.mutate(config, rng)
         }
 
         fn incr_num_offspring(&mut self, _n: usize) {
             self.num_offspring += 1
         }
+
+        fn gene_ages(&self) -> Option<&[usize]> {
+            Some(&self.chromosome.ages)
+        }
+
+        fn mutated_roles(&self) -> Vec<GeneRole> {
+            self.chromosome.mutated_roles()
+        }
+
+        fn generation(&self) -> usize {
+            self.chromosome.generation
+        }
     }
 
     impl Phenome for Creature {
         type Fitness = Fitness<'static>;
-        type Problem = ();
+        type Problem = RegisterCase;
 
         fn generate_description(&mut self) {
             self.description = Some(format!("{:#?}", self))
@@ -1516,9 +1555,28 @@ pub mod creature {
         }
 
         fn is_goal_reached(&self, config: &Config) -> bool {
-            self.scalar_fitness(&config.fitness.priority)
-                .map(|p| p - config.fitness.target <= std::f64::EPSILON)
-                .unwrap_or(false)
+            config.fitness.is_final_stage() && self.is_stage_complete(config)
+        }
+
+        fn fails(&self, case: &Self::Problem) -> bool {
+            self.case_error(case) > 0.0
+        }
+
+        fn case_error(&self, case: &Self::Problem) -> f64 {
+            self.profile.as_ref().map_or(1.0, |p| case.error(p))
+        }
+
+        /// The payload is kept, since the push VM is deterministic.
+        fn clear_development(&mut self) {
+            self.profile = None
+        }
+
+        fn gadgets_executed(&self) -> Option<&HashSet<u64>> {
+            self.profile.as_ref().map(|p| &p.gadgets_executed)
+        }
+
+        fn behavioural_distance(&self, other: &Self, measure: Distance) -> f64 {
+            self.profile_distance(other, measure)
         }
     }
 
@@ -5,6 +5,7 @@ use std::path::Path;
 
 use deflate::write::GzEncoder;
 use deflate::Compression;
+use flate2::read::GzDecoder;
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::error::Error;
@@ -26,12 +27,16 @@ pub fn dump<T: Serialize, P: AsRef<Path> + Debug>(thing: T, path: P) -> Result<(
     }
 }
 
+fn read_gzipped<P: AsRef<Path> + Debug>(path: P) -> Result<Vec<u8>, Error> {
+    let file = fs::File::open(&path)?;
+    let mut decompressed = Vec::new();
+    let num_bytes = GzDecoder::new(file).read_to_end(&mut decompressed)?;
+    log::debug!("{} bytes decompressed from {:?}", num_bytes, path);
+    Ok(decompressed)
+}
+
 pub fn undump<T: DeserializeOwned, P: AsRef<Path> + Debug>(path: P) -> Result<T, Error> {
-    let mut file = fs::File::open(&path)?;
-    let mut bytes = Vec::new();
-    let num_bytes = file.read_to_end(&mut bytes)?;
-    log::debug!("{} bytes read from {:?}", num_bytes, path);
-    let decompressed: Vec<u8> = deflate::deflate_bytes_gzip(&bytes);
+    let decompressed = read_gzipped(&path)?;
     let thing = serde_json::de::from_reader::<&[u8], T>(&decompressed)?;
     Ok(thing)
 }
@@ -55,11 +60,27 @@ pub fn ron_dump<T: Serialize, P: AsRef<Path> + Debug>(thing: T, path: P) -> Resu
 }
 
 pub fn ron_undump<T: DeserializeOwned, P: AsRef<Path> + Debug>(path: P) -> Result<T, Error> {
-    let mut file = fs::File::open(&path)?;
-    let mut bytes = Vec::new();
-    let num_bytes = file.read_to_end(&mut bytes)?;
-    log::debug!("{} bytes read from {:?}", num_bytes, path);
-    let decompressed: Vec<u8> = deflate::deflate_bytes_gzip(&bytes);
+    let decompressed = read_gzipped(&path)?;
     let thing = ron::de::from_reader::<&[u8], T>(&decompressed)?;
     Ok(thing)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dump_and_undump() {
+        let path =
+            std::env::temp_dir().join(format!("berbalang_dump_{}.json.gz", std::process::id()));
+        let thing = vec![(1_u64, "one".to_string()), (2, "two".to_string())];
+        dump(&thing, &path).expect("Failed to dump");
+        let undumped: Vec<(u64, String)> = undump(&path).expect("Failed to undump");
+        assert_eq!(undumped, thing);
+
+        ron_dump(&thing, &path).expect("Failed to dump as ron");
+        let undumped: Vec<(u64, String)> = ron_undump(&path).expect("Failed to undump ron");
+        assert_eq!(undumped, thing);
+        fs::remove_file(&path).expect("Failed to remove dump");
+    }
+}